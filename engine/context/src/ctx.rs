@@ -1,9 +1,5 @@
 use std::sync::Arc;
 
-use redis::Commands;
-
-const _REDIS_INTERNAL_KEY_: &str = "internals";
-
 #[derive(Debug)]
 pub struct CTX {
     pub redis_con: Arc<redis::Client>,
@@ -12,26 +8,39 @@ pub struct CTX {
 
 pub struct CTXOptions {
     _args: Vec<String>,
-    redis_host: &'static str,
-    rocks_db_name: &'static str
+    redis_host: String,
+    rocks_db_name: String
 }
 
 impl Default for CTXOptions {
     fn default() -> Self {
         Self { 
             _args: std::env::args().collect(), 
-            redis_host: "redis://127.0.0.1", 
-            rocks_db_name: "spy-db" 
+            redis_host: "redis://127.0.0.1".to_string(), 
+            rocks_db_name: "spy-db".to_string() 
         }
     }
 }
 
+impl CTXOptions {
+    pub fn redis_host(mut self, host: impl Into<String>) -> Self {
+        self.redis_host = host.into();
+        self
+    }
+
+    /// Path of the RocksDB directory; created when missing.
+    pub fn rocks_db_name(mut self, name: impl Into<String>) -> Self {
+        self.rocks_db_name = name.into();
+        self
+    }
+}
+
 impl CTX {
     #[allow(clippy::new_without_default)]
     pub fn new(options: CTXOptions) -> Self {
         print!("Connecting to Redis server... ");
         // init Redis client
-        let rcon = redis::Client::open(options.redis_host);
+        let rcon = redis::Client::open(options.redis_host.as_str());
         if rcon.is_err() {
             eprintln!("Couldn't connect to Redis server! Try again.");
             std::process::exit(1);
@@ -43,7 +52,7 @@ impl CTX {
         // init RocksDB client
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
-        let rocks_con = rocksdb::DB::open_default(options.rocks_db_name).expect("Couldn't connect to RocksDB server! Try again.");
+        let rocks_con = rocksdb::DB::open(&opts, &options.rocks_db_name).expect("Couldn't connect to RocksDB server! Try again.");
 
         println!("DONE!");
 
@@ -53,32 +62,24 @@ impl CTX {
         }
     }
 
-    pub fn incr_doc_count(&self) -> redis::RedisResult<()> {
-        let mut con = self.redis_con.get_connection()?;
-        con.hincr(_REDIS_INTERNAL_KEY_, "total_docs", 1)
-    }
-
-    pub fn get_internal_value<T>(&self, internal_key: &str) -> Option<T>
-    where 
-        T: redis::FromRedisValue
-    {
-        let mut conn = self.redis_con.get_connection().ok()?;
-
-        let result = conn.hget(_REDIS_INTERNAL_KEY_, internal_key);
-        result.ok()
-    }
-
-    pub fn get_partially_matching_keys(db: &rocksdb::DB, search_query: &str) -> Vec<String> {
+    /// Keys starting with `prefix` that contain `search_query`. Keys that
+    /// aren't UTF-8 are skipped.
+    pub fn get_partially_matching_keys(db: &rocksdb::DB, prefix: &str, search_query: &str) -> Vec<String> {
         let mut similar_keys: Vec<String> = Vec::new();
-        let db_key_iter = db.iterator(rocksdb::IteratorMode::Start);
+        let db_key_iter = db.prefix_iterator(prefix);
 
         for key_value in db_key_iter {
             match key_value {
                 Ok((key, _value)) => {
-                    let key_str: String = String::from_utf8(key.to_vec()).expect("Invalid UTF-8 for String::from_utf8");
+                    if !key.starts_with(prefix.as_bytes()) {
+                        break;
+                    }
+                    let Ok(key_str) = std::str::from_utf8(&key) else {
+                        continue;
+                    };
 
                     if key_str.contains(search_query) {
-                        similar_keys.push(key_str);
+                        similar_keys.push(key_str.to_string());
                     }
                 },
                 Err(_) => eprintln!("Lookup failed!")
@@ -1,5 +1,3 @@
 mod ctx;
 
-pub use ctx::*;
-
-pub const REDIS_TERMS_KEY: &str = "term_frequencies";
\ No newline at end of file
+pub use ctx::*;
\ No newline at end of file
@@ -1,24 +1,118 @@
 use std::{
-    collections::{
-        HashSet,
-        VecDeque
-    }, 
-    error::Error, 
+    collections::HashSet, 
     sync::{
-        Arc, 
-        Mutex
+        atomic::Ordering, 
+        Arc
     }
 };
 
-use indexer::Indexer;
+use indexer::{errors::IndexerError, Document, Indexer};
 
-use crate::HtmlDoc;
+use crate::{
+    content_hash, 
+    days_since_epoch, 
+    errors::CrawlerError, 
+    check_html, 
+    decode_body, 
+    fetch_sitemap, 
+    normalize_url, 
+    read_body, 
+    send_with_retries, 
+    now_secs, 
+    FailureLog, 
+    FetchMeta, 
+    FetchMetaStore, 
+    FetchOptions, 
+    Frontier, 
+    FrontierEntry, 
+    visit_key, 
+    HtmlDoc, 
+    JobControl, 
+    JobState, 
+    Link, 
+    LinkGraph, 
+    PolitePermit, 
+    Politeness, 
+    PolitenessOptions, 
+    RobotsCache, 
+    RobotsDirectives, 
+    Scope, 
+    ScopeOptions, 
+    Sitemap, 
+    SkippedLink, 
+    TextExtraction
+};
+
+/// Pages crawled between two checkpoints of the frontier.
+const CHECKPOINT_INTERVAL: usize = 25;
+
+/// Sitemaps fetched per crawl at most, indexes included.
+const MAX_SITEMAPS: usize = 100;
+
+/// Sent with every request, robots.txt fetches included.
+pub const DEFAULT_USER_AGENT: &str = "SpyBot/0.1 (search engine crawler)";
 
 #[derive(Debug, Clone)]
 pub struct CrawlerOptions {
     pub max_depth: usize,
 
     pub seed_url: String,
+
+    /// Name the crawler identifies itself with, and the one matched
+    /// against `User-agent` lines in robots.txt.
+    pub user_agent: String,
+
+    /// Rate and concurrency limits, per host and overall.
+    pub politeness: PolitenessOptions,
+
+    /// Which discovered links are followed.
+    pub scope: ScopeOptions,
+
+    /// Timeouts, retries and size limit of requests.
+    pub fetch: FetchOptions,
+
+    /// Client to send requests with, e.g. one shared by several crawls.
+    /// `None` builds one from `user_agent` and `fetch`.
+    pub client: Option<reqwest::Client>,
+
+    /// The frontier is checkpointed under this id as the crawl goes, and
+    /// a crawl started with the id of an unfinished one resumes it.
+    /// `None` keeps the crawl in memory.
+    pub job_id: Option<u64>,
+
+    /// Also queue the pages listed in the seed site's sitemaps.
+    pub use_sitemaps: bool,
+
+    /// Also queue the pages in scope that are due for a revisit, see
+    /// `RecrawlScheduler`.
+    pub recrawl: bool,
+
+    /// Index and follow everything, ignoring `noindex` and `nofollow` in
+    /// `<meta name="robots">`, `X-Robots-Tag` and `rel="nofollow"`; for
+    /// sites we own. robots.txt is still obeyed.
+    pub ignore_robots_directives: bool,
+
+    /// Which text of a page is indexed as its body.
+    pub text_extraction: TextExtraction
+}
+
+impl Default for CrawlerOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 1,
+            seed_url: String::new(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            politeness: PolitenessOptions::default(),
+            scope: ScopeOptions::default(),
+            fetch: FetchOptions::default(),
+            client: None,
+            job_id: None,
+            use_sitemaps: true,
+            recrawl: false,
+            ignore_robots_directives: false,
+            text_extraction: TextExtraction::default()
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,111 +122,839 @@ pub struct Crawler {
     /// Options
     options: CrawlerOptions,
 
-    links_to_crawl: Arc<Mutex<VecDeque<String>>>,
+    /// Links to crawl and pages already seen.
+    frontier: Arc<Frontier>,
+
+    /// What earlier fetches of each page found.
+    fetch_meta: FetchMetaStore,
+
+    /// URLs that failed, by the error they failed with.
+    failures: FailureLog,
+
+    /// Links found on each crawled page.
+    graph: LinkGraph,
+
+    /// State and counters of the crawl, see `JobRegistry`.
+    control: Arc<JobControl>,
+
+    client: reqwest::Client,
+
+    robots: Arc<RobotsCache>,
+
+    politeness: Arc<Politeness>,
 
-    visited: Arc<Mutex<HashSet<String>>>
+    scope: Arc<Scope>
 }
 
 impl Crawler {
     pub fn new(indexer: Arc<Indexer>, opts: CrawlerOptions) -> Self {
-        let mut initial_links = VecDeque::new();
-        initial_links.push_back(opts.seed_url.clone());
+        Self::with_control(indexer, opts, Arc::new(JobControl::default()))
+    }
+
+    /// A crawler that reports to, and can be paused or cancelled through, `control`.
+    pub fn with_control(indexer: Arc<Indexer>, opts: CrawlerOptions, control: Arc<JobControl>) -> Self {
+        let frontier = Arc::new(Frontier::new(indexer.ctx().rocks_con.clone(), opts.job_id, opts.politeness.max_per_host));
+
+        let fetch_meta = FetchMetaStore::new(indexer.ctx().rocks_con.clone());
+        let failures = FailureLog::new(indexer.ctx().rocks_con.clone());
+        let graph = LinkGraph::new(indexer.ctx().rocks_con.clone());
+
+        let client: reqwest::Client = opts.client.clone().unwrap_or_else(|| opts.fetch.client(&opts.user_agent));
+        let robots = Arc::new(RobotsCache::new(client.clone(), opts.user_agent.clone(), opts.fetch));
+        let politeness = Arc::new(Politeness::new(opts.politeness));
+        let scope = Arc::new(Scope::new(opts.scope.clone(), &opts.seed_url));
 
         Self {
             options: opts,
             index: indexer,
-            links_to_crawl: Arc::new(Mutex::new(initial_links)),
-            visited: Arc::new(Mutex::new(HashSet::new()))
+            frontier,
+            fetch_meta,
+            failures,
+            graph,
+            control,
+            client,
+            robots,
+            politeness,
+            scope
         }
     }
 
-    pub async fn start_crawling(&self) -> Result<(), Box<dyn Error>> {
-        let mut depth: usize = 0;
-
-        while depth <= self.options.max_depth {
-            let mut handles = vec![];
-            let mut batch = vec![];
-
-            {
-                let mut queue = self.links_to_crawl.lock().unwrap();
-                let mut seen = self.visited.lock().unwrap();
+    pub async fn start_crawling(&self) -> Result<(), CrawlerError> {
+        // a job cancelled while still queued never starts
+        if self.control.transition(JobState::Running).is_err() {
+            return Ok(());
+        }
 
-                while let Some(link) = queue.pop_front() {
-                    if !seen.contains(&link) {
-                        seen.insert(link.clone());
-                        batch.push(link.clone());
+        if !self.frontier.restore()? {
+            let seed: String = normalize_url(&self.options.seed_url).unwrap_or_else(|| self.options.seed_url.clone());
+            match self.robots.check(&seed).await {
+                Ok(()) => {
+                    if self.options.use_sitemaps && self.options.max_depth > 0 {
+                        self.queue_sitemaps(&seed).await;
                     }
+                    self.frontier.push(FrontierEntry::new(seed, 0));
                 }
+                Err(err) => self.record_failure(err)
             }
+            if self.options.recrawl {
+                self.queue_due_pages();
+            }
+        }
 
-            for link in batch {
-                if let Ok(handle) = Self::crawl_link(link, self.options.seed_url.clone(), self.links_to_crawl.clone()).await {
-                    handles.push(handle);
-                }
+        let workers: usize = self.options.politeness.max_concurrency.max(1);
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut since_checkpoint: usize = 0;
+
+        loop {
+            if !self.control.wait_until_runnable().await {
+                break;
             }
 
-            for handle in handles {
-                let _ = handle.await;
+            // refill every free worker slot, a slow page only holds up its own
+            while tasks.len() < workers {
+                let Some(entry) = self.frontier.pop(|url| self.scope.claim_page(url)) else {
+                    break;
+                };
+                self.control.current_depth.store(entry.depth, Ordering::Relaxed);
+
+                let crawler: Crawler = self.clone();
+                tasks.spawn(async move { crawler.crawl_link(entry).await });
+            }
+            self.control.pages_queued.store(self.frontier.len(), Ordering::Relaxed);
+
+            // nothing running means nothing left to crawl
+            if tasks.join_next().await.is_none() {
+                break;
+            }
+            since_checkpoint += 1;
+            if since_checkpoint >= CHECKPOINT_INTERVAL {
+                self.checkpoint();
+                since_checkpoint = 0;
             }
-            depth += 1;
         }
 
+        // a cancelled job lets the fetches already running finish
+        while tasks.join_next().await.is_some() {}
+        if let Err(err) = self.index.reindex_anchors() {
+            eprintln!("Failed to index the anchor text found by the crawl of {}: {}", self.options.seed_url, err);
+        }
+
+        // a cancelled job keeps its checkpoint, so it can be resumed by id
+        if self.control.state() == JobState::Cancelled {
+            self.checkpoint();
+            return Ok(());
+        }
+        if let Err(err) = self.frontier.finish() {
+            eprintln!("Failed to clear the checkpoint of {}: {}", self.options.seed_url, err);
+        }
+        self.control.pages_queued.store(0, Ordering::Relaxed);
+        _ = self.control.transition(JobState::Done);
         Ok(())
     }
 
-    pub async fn crawl_link(
-        link: String, 
-        seed_url: String, 
-        links_to_crawl: Arc<Mutex<VecDeque<String>>>
-    ) -> Result<tokio::task::JoinHandle<()>, Box<dyn Error>> {
-        let handle = tokio::task::spawn(async move {
-            let links = if let Ok(page) = Self::fetch_html(link).await {
-                page.extract_links()
+    /// Fetches and indexes the page of `entry`, then queues the links on
+    /// it that are in scope and allowed by robots.txt.
+    pub async fn crawl_link(&self, entry: FrontierEntry) {
+        // left in flight, so the next checkpoint keeps it for a resumed crawl
+        if !self.control.wait_until_runnable().await {
+            return;
+        }
+
+        let links: Vec<String> = self.fetch_and_index(&entry).await;
+
+        // the entry's own depth decides, however late its links get crawled
+        let depth: usize = entry.depth + 1;
+        if depth <= self.options.max_depth {
+            for href in links {
+                if self.scope.admits(&href) && self.robots.is_allowed(&href).await {
+                    self.frontier.push(FrontierEntry {
+                        inlinks: 1,
+                        referrer: Some(entry.url.clone()),
+                        ..FrontierEntry::new(href, depth)
+                    });
+                }
             }
-            else {
-                return;
+        }
+        self.frontier.complete(&entry);
+    }
+
+    /// Fetches and indexes the page of `entry` and returns its links,
+    /// normalized and recorded in the link graph; none when the fetch
+    /// failed, which is recorded, or the page is a duplicate. A page that
+    /// didn't change since the last crawl isn't indexed again, and its
+    /// links are the ones it had then. Robots directives are honored
+    /// unless the options say otherwise: `noindex` pages aren't indexed,
+    /// and `nofollow` pages and links aren't followed.
+    async fn fetch_and_index(&self, entry: &FrontierEntry) -> Vec<String> {
+        let mut meta: FetchMeta = match self.fetch_meta.get(&entry.url) {
+            Ok(meta) => meta.unwrap_or_default(),
+            Err(err) => {
+                eprintln!("Failed to read fetch metadata of {}: {}", entry.url, err);
+                FetchMeta::default()
+            }
+        };
+
+        // the sitemap says the page hasn't changed since it was fetched
+        let fetched_day: i64 = (meta.last_fetched / 86_400) as i64;
+        if meta.fetches > 0 && entry.lastmod.as_deref().and_then(days_since_epoch).is_some_and(|day| day < fetched_day) {
+            self.control.pages_unchanged.fetch_add(1, Ordering::Relaxed);
+            return self.known_links(&entry.url, &meta);
+        }
+
+        // parsed pages aren't `Send`, so they must be gone before the next await
+        let (page_url, links, skipped): (String, Vec<Link>, Vec<SkippedLink>) = {
+            let _permit = self.wait_for_turn(&entry.url).await;
+            let fetched = Self::fetch_html(&self.client, &self.options, entry.url.clone(), &mut meta).await;
+            if fetched.is_ok() {
+                if let Err(err) = self.failures.clear(self.options.job_id, &entry.url) {
+                    eprintln!("Failed to clear the recorded failure of {}: {}", entry.url, err);
+                }
+            }
+
+            let mut page: HtmlDoc = match fetched {
+                Ok(Some(page)) => {
+                    self.control.record_fetch(true);
+                    page
+                }
+                Ok(None) => {
+                    self.control.pages_unchanged.fetch_add(1, Ordering::Relaxed);
+                    self.save_fetch_meta(&entry.url, &meta);
+                    return self.known_links(&entry.url, &meta);
+                }
+                Err(err) => {
+                    self.control.record_fetch(false);
+                    self.record_failure(err);
+                    meta.record_failure(now_secs());
+                    self.save_fetch_meta(&entry.url, &meta);
+                    return vec![];
+                }
             };
+            if !self.claim_canonical(&mut page) {
+                meta.source = None;
+                self.save_fetch_meta(&entry.url, &meta);
+                return vec![];
+            }
+            let directives: RobotsDirectives = if self.options.ignore_robots_directives {
+                RobotsDirectives::default()
+            } else {
+                page.robots
+            };
+            Self::create_index(&self.index, &mut page, directives.noindex);
+            let (mut links, skipped): (Vec<Link>, Vec<SkippedLink>) = page.resolve_links();
+            if directives.nofollow {
+                links.clear();
+            }
+            (page.url.clone(), links, skipped)
+        };
 
-            let mut queue = links_to_crawl.lock().unwrap();
+        self.control.links_skipped.fetch_add(skipped.len(), Ordering::Relaxed);
 
-            for href in links {
-                let href_parsed = if url::Url::parse(&href).is_ok() {
-                    href
+        let anchors: Vec<(String, String)> = links.into_iter()
+            .filter(|link| self.options.ignore_robots_directives || !link.is_nofollow())
+            .filter_map(|link| Some((normalize_url(&link.url)?, link.anchor_text)))
+            .collect();
+        let mut links: Vec<String> = anchors.iter().map(|(url, _)| url.clone()).collect();
+        links.sort();
+        links.dedup();
+
+        let source: String = normalize_url(&page_url).unwrap_or(page_url);
+        if let Err(err) = self.graph.set_outlinks(&source, &links) {
+            eprintln!("Failed to store the links of {}: {}", source, err);
+        }
+        if let Err(err) = self.index.set_anchors(&source, &anchors) {
+            eprintln!("Failed to index the anchor text of {}: {}", source, err);
+        }
+
+        meta.source = Some(source);
+        self.save_fetch_meta(&entry.url, &meta);
+        links
+    }
+
+    /// Links of a page that didn't change, as recorded when it last did.
+    fn known_links(&self, url: &str, meta: &FetchMeta) -> Vec<String> {
+        let Some(source) = meta.source.clone().or_else(|| normalize_url(url)) else {
+            return vec![];
+        };
+        self.graph.outlinks(&source).unwrap_or_else(|err| {
+            eprintln!("Failed to read the stored links of {}: {}", source, err);
+            vec![]
+        })
+    }
+
+    /// Logs `err` and keeps it in the failure log, under the job's id.
+    /// Only fetch failures are kept; storage errors are just logged.
+    fn record_failure(&self, err: CrawlerError) {
+        eprintln!("{}", err);
+        if let Err(log_err) = self.failures.record(self.options.job_id, &err) {
+            eprintln!("Failed to record the failure in the crawl of {}: {}", self.options.seed_url, log_err);
+        }
+    }
+
+    fn save_fetch_meta(&self, url: &str, meta: &FetchMeta) {
+        if let Err(err) = self.fetch_meta.put(url, meta) {
+            eprintln!("Failed to save fetch metadata of {}: {}", url, err);
+        }
+    }
+
+    /// Queues the pages in scope that are due for a revisit, with the
+    /// inlinks earlier crawls found to them.
+    fn queue_due_pages(&self) {
+        match self.fetch_meta.due(now_secs()) {
+            Ok(due) => {
+                for url in due.into_iter().filter(|url| self.scope.admits(url)) {
+                    let inlinks: usize = self.graph.inlinks(&url).map_or(0, |sources| sources.len());
+                    self.frontier.push(FrontierEntry { inlinks, ..FrontierEntry::new(url, 0) });
                 }
-                else {
-                    let base_url: url::Url = url::Url::parse(&seed_url).expect("Invalid base URL");
-                    let abs_url: url::Url = base_url.join(&href).expect("Invalid URL formation!");
-                    abs_url.as_str().to_string()
-                };
-    
-                queue.push_back(href_parsed);
             }
-        });
-        Ok(handle)
+            Err(err) => eprintln!("Failed to list the pages due for a recrawl: {}", err)
+        }
     }
 
-    /// Creates a HtmlDoc given a link.
-    async fn fetch_html(link: String) -> Result<HtmlDoc, Box<dyn Error>> {
-        let client = reqwest::Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.3")
-            .build()
-            .expect("Failed to create client");
+    /// Queues the pages listed in the sitemaps of the seed's site, one
+    /// link away from the seed. The sitemaps are the ones robots.txt
+    /// names and `/sitemap.xml`; sitemap indexes are followed to the
+    /// sitemaps in scope that robots.txt allows.
+    async fn queue_sitemaps(&self, seed: &str) {
+        let Ok(seed) = url::Url::parse(seed) else {
+            return;
+        };
+
+        // (sitemap, whether the site listed it); a missing /sitemap.xml isn't worth a warning
+        let mut pending: Vec<(String, bool)> = self.robots.get(&seed).await.sitemaps().iter()
+            .filter_map(|loc| seed.join(loc).ok())
+            .map(|loc| (loc.to_string(), true))
+            .collect();
+        // taken last, so a sitemap robots.txt lists under the same URL counts as listed
+        pending.insert(0, (format!("{}/sitemap.xml", seed.origin().ascii_serialization()), false));
+
+        let today: i64 = (now_secs() / 86_400) as i64;
+        let mut fetched: HashSet<String> = HashSet::new();
+        while let Some((sitemap_url, listed)) = pending.pop() {
+            if fetched.len() >= MAX_SITEMAPS || !fetched.insert(sitemap_url.clone()) {
+                continue;
+            }
 
-        let resp = client.get(link.clone()).send().await?;
+            let sitemap: Result<Sitemap, CrawlerError> = {
+                let _permit = self.wait_for_turn(&sitemap_url).await;
+                fetch_sitemap(&self.client, &sitemap_url, &self.options.fetch).await
+            };
+            let base: Option<url::Url> = url::Url::parse(&sitemap_url).ok();
+            let resolve = |loc: &str| base.as_ref()?.join(loc).ok().and_then(|url| normalize_url(url.as_str()));
+
+            match sitemap {
+                Ok(Sitemap::Index(locs)) => {
+                    for loc in locs.iter().filter_map(|loc| resolve(loc)) {
+                        if self.scope.admits(&loc) && self.robots.is_allowed(&loc).await {
+                            pending.push((loc, true));
+                        }
+                    }
+                }
+                Ok(Sitemap::Urls(urls)) => {
+                    for url in urls {
+                        let Some(loc) = resolve(&url.loc) else {
+                            continue;
+                        };
+                        if self.scope.admits(&loc) && self.robots.is_allowed(&loc).await {
+                            let value: f64 = url.value(today);
+                            self.frontier.push(FrontierEntry {
+                                value,
+                                lastmod: url.lastmod,
+                                referrer: Some(sitemap_url.clone()),
+                                ..FrontierEntry::new(loc, 1)
+                            });
+                        }
+                    }
+                }
+                Err(err) if listed => self.record_failure(err),
+                Err(_) => {}
+            }
+        }
+    }
+
+    fn checkpoint(&self) {
+        if let Err(err) = self.frontier.checkpoint() {
+            eprintln!("Failed to checkpoint the crawl of {}: {}", self.options.seed_url, err);
+        }
+    }
 
+    /// Honors `<link rel="canonical">`: the page is indexed under its
+    /// canonical URL, which is marked visited. Returns `false` when that
+    /// URL was already crawled, i.e. the page is a duplicate.
+    fn claim_canonical(&self, page: &mut HtmlDoc) -> bool {
+        let canonical: Option<String> = page.canonical_url.as_deref()
+            .and_then(|href| url::Url::parse(&page.url).ok()?.join(href).ok())
+            .and_then(|url| normalize_url(url.as_str()))
+            .filter(|url| self.scope.admits(url));
+
+        let (Some(canonical), Some(own_key)) = (canonical, visit_key(&page.url)) else {
+            return true;
+        };
+        let Some(key) = visit_key(&canonical) else {
+            return true;
+        };
+
+        if key != own_key && !self.frontier.mark_seen(key) {
+            return false;
+        }
+        page.url = canonical;
+        true
+    }
+
+    /// Waits until politeness allows fetching `link` from its host.
+    async fn wait_for_turn(&self, link: &str) -> Option<PolitePermit> {
+        let url: url::Url = url::Url::parse(link).ok()?;
+        let host: &str = url.host_str()?;
+
+        let crawl_delay = self.robots.crawl_delay(&url).await;
+        Some(self.politeness.acquire(host, crawl_delay).await)
+    }
+
+    /// Creates a HtmlDoc given a link, asking the server to skip the body
+    /// if the page didn't change since `meta` was recorded. `None` when it
+    /// didn't: the server answered 304 Not Modified or sent the same
+    /// content again. `meta` is updated with this fetch either way.
+    /// Responses that aren't HTML are refused before their body is read.
+    /// The page's robots directives include those of `X-Robots-Tag`.
+    async fn fetch_html(client: &reqwest::Client, options: &CrawlerOptions, link: String, meta: &mut FetchMeta) -> Result<Option<HtmlDoc>, CrawlerError> {
+        let mut request = client.get(link.clone());
+        if let Some(etag) = &meta.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &meta.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let resp = send_with_retries(request, &link, &options.fetch).await?;
+
+        if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+            meta.record(false, now_secs());
+            return Ok(None);
+        }
         if !resp.status().is_success() {
-            return Err(format!("Failed to fetch: {}", &link).into());
+            return Err(CrawlerError::Status { url: link, status: resp.status().as_u16() });
         }
+        check_html(&resp, &link)?;
+
+        let header = |name: reqwest::header::HeaderName| {
+            resp.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+        };
+        meta.etag = header(reqwest::header::ETAG);
+        meta.last_modified = header(reqwest::header::LAST_MODIFIED);
+        let robots: RobotsDirectives = resp.headers().get_all("x-robots-tag").iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|value| RobotsDirectives::from_header(value, &options.user_agent))
+            .fold(RobotsDirectives::default(), RobotsDirectives::union);
 
-        let page_body: String = resp.text().await?;
-        Ok(HtmlDoc::parse(link, page_body))
+        let final_url: String = resp.url().to_string();
+        let content_type: Option<String> = header(reqwest::header::CONTENT_TYPE);
+        let body: Vec<u8> = read_body(resp, &link, options.fetch.max_body_bytes).await?;
+        let page_body: String = decode_body(&body, content_type.as_deref());
+
+        let hash: u64 = content_hash(page_body.as_bytes());
+        let changed: bool = meta.content_hash != Some(hash);
+        meta.content_hash = Some(hash);
+        meta.record(changed, now_secs());
+
+        if !changed {
+            return Ok(None);
+        }
+        let mut page = HtmlDoc::parse(final_url, page_body);
+        page.robots = page.robots.union(robots);
+        page.text_extraction = options.text_extraction;
+        Ok(Some(page))
     }
 
-    fn create_index(_page: HtmlDoc) {
+    /// Indexes `page`; a `noindex` page is removed from the index instead.
+    fn create_index(indexer: &Indexer, page: &mut HtmlDoc, noindex: bool) {
+        let doc = Document {
+            url: page.url.clone(),
+            title: page.title.clone(),
+            text: page.text().unwrap_or_default().to_string(),
+            headings: page.headings.clone(),
+            description: page.description.clone().unwrap_or_default(),
+            link_url: normalize_url(&page.url).filter(|url| *url != page.url),
+            noindex,
+            ..Document::default()
+        };
 
+        match indexer.create_index(&doc) {
+            Ok(_) | Err(IndexerError::NoIndex(_)) => {}
+            Err(err) => eprintln!("Failed to index {}: {}", page.url, err)
+        }
     }
 
     pub fn max_depth(&self) -> usize {
         self.options.max_depth
     }
-}
\ No newline at end of file
+
+    pub fn control(&self) -> &Arc<JobControl> {
+        &self.control
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexer::IndexReader;
+    use testkit::TestCtx;
+
+    use crate::MIN_RECRAWL_INTERVAL;
+
+    use super::*;
+
+    /// Serves `pages` (path, body) on a local port and returns its origin.
+    /// Unknown paths answer 404. Pages carry an ETag, and a request that
+    /// sends it back gets a 304.
+    fn serve_site(pages: Vec<(&'static str, String)>) -> String {
+        testkit::serve(move |request| {
+            let if_none_match: Option<String> = request.headers().iter()
+                .find(|header| header.field.equiv("If-None-Match"))
+                .map(|header| header.value.to_string());
+
+            match pages.iter().find(|(path, _)| *path == request.url()) {
+                Some((_, body)) => {
+                    let etag = format!("\"{:x}\"", content_hash(body.as_bytes()));
+                    let status: u16 = if if_none_match.as_deref() == Some(etag.as_str()) { 304 } else { 200 };
+                    tiny_http::Response::from_string(if status == 304 { String::new() } else { body.clone() })
+                        .with_status_code(status)
+                        .with_header(tiny_http::Header::from_bytes("Content-Type", "text/html").unwrap())
+                        .with_header(tiny_http::Header::from_bytes("ETag", etag).unwrap())
+                        .boxed()
+                }
+                None => tiny_http::Response::from_string("").with_status_code(404).boxed()
+            }
+        })
+    }
+
+    /// A crawler of the site at `origin` on a fresh database, which lives
+    /// as long as the returned context. It starts from the front page and
+    /// doesn't slow down for politeness; the rest comes from `options`.
+    fn test_crawler(name: &str, origin: &str, options: CrawlerOptions) -> (TestCtx, Crawler) {
+        let (ctx, indexer) = TestCtx::with(&format!("crawler-{}", name), |ctx| Arc::new(Indexer::new(ctx)));
+        let crawler = Crawler::new(indexer, CrawlerOptions {
+            seed_url: format!("{}/", origin),
+            politeness: PolitenessOptions { requests_per_second: 100.0, ..PolitenessOptions::default() },
+            ..options
+        });
+        (ctx, crawler)
+    }
+
+    fn page(title: &str, links: &[&str]) -> String {
+        let links: String = links.iter().map(|href| format!(r#"<a href="{}">link</a>"#, href)).collect();
+        format!("<html><head><title>{}</title></head><body><p>Some text.</p>{}</body></html>", title, links)
+    }
+
+    #[tokio::test]
+    async fn test_crawl_respects_robots_txt() {
+        let origin = serve_site(vec![
+            ("/robots.txt", "User-agent: *\nDisallow: /private\n\nUser-agent: spybot\nDisallow: /private\nDisallow: /bots-only".to_string()),
+            ("/", page("Home", &["/public", "/private", "/bots-only"])),
+            ("/public", page("Public", &[])),
+            ("/private", page("Private", &[])),
+            ("/bots-only", page("Bots", &[]))
+        ]);
+
+        let (ctx, crawler) = test_crawler("robots", &origin, CrawlerOptions::default());
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        assert!(reader.doc_id(&format!("{}/public", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/private", origin)).unwrap().is_none());
+        assert!(reader.doc_id(&format!("{}/bots-only", origin)).unwrap().is_none());
+        assert_eq!(crawler.control().state(), JobState::Done);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_dedups_normalized_and_canonical_urls() {
+        let origin = serve_site(vec![
+            ("/", page("Home", &["/page.html", "/page.html#section", "/./page.html?utm_source=x", "/print"])),
+            ("/page.html", page("Page", &["/"])),
+            ("/print", r#"<html><head><link rel="canonical" href="/page.html"></head><body><p>Printable.</p></body></html>"#.to_string())
+        ]);
+
+        let (ctx, crawler) = test_crawler("canonical", &origin, CrawlerOptions::default());
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        assert_eq!(reader.doc_ids().unwrap().len(), 2);
+        assert!(reader.doc_id(&format!("{}/page.html", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/print", origin)).unwrap().is_none());
+        assert_eq!(crawler.control().pages_fetched.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_links_resolve_against_the_page_they_are_on() {
+        let origin = serve_site(vec![
+            ("/", page("Home", &["/docs/guide/intro.html"])),
+            ("/docs/guide/intro.html", page("Intro", &["next.html", "http://[broken"])),
+            ("/docs/guide/next.html", page("Next", &[])),
+            ("/next.html", page("Wrong", &[]))
+        ]);
+
+        let (ctx, crawler) = test_crawler("relative", &origin, CrawlerOptions {
+            max_depth: 2,
+            ..CrawlerOptions::default()
+        });
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        assert!(reader.doc_id(&format!("{}/docs/guide/next.html", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/next.html", origin)).unwrap().is_none());
+        assert_eq!(crawler.control().links_skipped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_depth_is_enforced_per_entry_and_links_are_recorded() {
+        let origin = serve_site(vec![
+            ("/", page("Home", &["/a", "/a#top"])),
+            ("/a", page("A", &["/b"])),
+            ("/b", page("B", &["/c"]))
+        ]);
+
+        let (ctx, crawler) = test_crawler("depth", &origin, CrawlerOptions {
+            max_depth: 1,
+            ..CrawlerOptions::default()
+        });
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        assert!(reader.doc_id(&format!("{}/a", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/b", origin)).unwrap().is_none());
+
+        // links are recorded whether or not they get crawled
+        let graph = LinkGraph::new(ctx.rocks_con.clone());
+        assert_eq!(graph.outlinks(&format!("{}/", origin)).unwrap(), vec![format!("{}/a", origin)]);
+        assert_eq!(graph.outlinks(&format!("{}/a", origin)).unwrap(), vec![format!("{}/b", origin)]);
+        assert!(graph.outlinks(&format!("{}/b", origin)).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_anchor_text_is_indexed_for_link_targets() {
+        let origin = serve_site(vec![
+            ("/", r#"<html><body><p>Welcome.</p><a href="/guide">Python tutorial</a></body></html>"#.to_string()),
+            ("/guide", r#"<html><body><p>Loops.</p><a href="/">Frontpage</a></body></html>"#.to_string())
+        ]);
+
+        let (ctx, crawler) = test_crawler("anchors", &origin, CrawlerOptions::default());
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        let guide: u64 = reader.doc_id(&format!("{}/guide", origin)).unwrap().unwrap();
+        let tutorial = reader.postings("tutorial").unwrap();
+        assert_eq!(tutorial.len(), 1);
+        assert_eq!(tutorial[0].doc_id, guide);
+        assert_eq!(tutorial[0].field_tf[indexer::Field::Anchor.index()], 1);
+
+        // the seed was indexed before the guide linked back to it
+        let home: u64 = reader.doc_id(&format!("{}/", origin)).unwrap().unwrap();
+        assert_eq!(reader.postings("frontpage").unwrap()[0].doc_id, home);
+    }
+
+    #[tokio::test]
+    async fn test_headings_are_not_counted_as_body_text() {
+        let origin = serve_site(vec![
+            ("/", "<html><body><h1>Generators</h1><p>Lazy sequences.</p></body></html>".to_string())
+        ]);
+
+        let (ctx, crawler) = test_crawler("headings", &origin, CrawlerOptions::default());
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        let postings = reader.postings("generators").unwrap();
+        assert_eq!(postings.len(), 1);
+        assert_eq!(postings[0].field_tf[indexer::Field::Headings.index()], 1);
+        assert_eq!(postings[0].field_tf[indexer::Field::Body.index()], 0);
+        assert_eq!(reader.postings("lazy").unwrap()[0].field_tf[indexer::Field::Body.index()], 1);
+    }
+
+    #[tokio::test]
+    async fn test_robots_directives_are_honored_unless_ignored() {
+        let origin = serve_site(vec![
+            ("/", r#"<html><body><p>Home.</p><a href="/hidden">Hidden</a><a href="/ad" rel="sponsored nofollow">Ad</a><a href="/closed">Closed</a></body></html>"#.to_string()),
+            ("/hidden", r#"<html><head><meta name="robots" content="noindex"></head><body><a href="/deep">Deep</a></body></html>"#.to_string()),
+            ("/closed", r#"<html><head><meta name="robots" content="nofollow"></head><body><a href="/unreached">Unreached</a></body></html>"#.to_string()),
+            ("/ad", page("Ad", &[])),
+            ("/deep", page("Deep", &[])),
+            ("/unreached", page("Unreached", &[]))
+        ]);
+
+        for ignore_robots_directives in [false, true] {
+            let (ctx, crawler) = test_crawler(&format!("directives-{}", ignore_robots_directives), &origin, CrawlerOptions {
+                max_depth: 2,
+                ignore_robots_directives,
+                ..CrawlerOptions::default()
+            });
+            crawler.start_crawling().await.unwrap();
+
+            let reader = IndexReader::new(&ctx);
+            let indexed = |path: &str| reader.doc_id(&format!("{}{}", origin, path)).unwrap().is_some();
+            assert!(indexed("/closed"));
+            assert!(indexed("/deep"), "links of noindex pages are still followed");
+            assert_eq!(indexed("/hidden"), ignore_robots_directives);
+            assert_eq!(indexed("/ad"), ignore_robots_directives);
+            assert_eq!(indexed("/unreached"), ignore_robots_directives);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_crawl_resumes_from_checkpoint() {
+        let origin = serve_site(vec![
+            ("/", page("Home", &["/a", "/b"])),
+            ("/a", page("A", &["/"])),
+            ("/b", page("B", &[]))
+        ]);
+        let (ctx, resumed) = test_crawler("resume", &origin, CrawlerOptions {
+            job_id: Some(3),
+            ..CrawlerOptions::default()
+        });
+
+        // as left by an earlier run that crawled the seed, then stopped
+        let frontier = Frontier::new(ctx.rocks_con.clone(), Some(3), 2);
+        frontier.push(FrontierEntry::new(format!("{}/", origin), 0));
+        let seed = frontier.pop(|_| true).unwrap();
+        frontier.push(FrontierEntry::new(format!("{}/a", origin), 1));
+        frontier.push(FrontierEntry::new(format!("{}/b", origin), 1));
+        frontier.complete(&seed);
+        frontier.checkpoint().unwrap();
+
+        resumed.start_crawling().await.unwrap();
+        assert_eq!(resumed.control().pages_fetched.load(Ordering::Relaxed), 2);
+
+        let reader = IndexReader::new(&ctx);
+        assert!(reader.doc_id(&format!("{}/a", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/", origin)).unwrap().is_none());
+
+        // the finished job leaves no checkpoint behind, only its id
+        let keys: Vec<String> = ctx.rocks_con.prefix_iterator("crawl:")
+            .map(|key_value| String::from_utf8(key_value.unwrap().0.into_vec()).unwrap())
+            .take_while(|key| key.starts_with("crawl:"))
+            .collect();
+        assert_eq!(keys, vec!["crawl:00000000000000000003:done"]);
+        assert_eq!(Frontier::last_job_id(&ctx.rocks_con), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_crawl_resumes_by_id() {
+        let control = Arc::new(JobControl::default());
+        let cancel = control.clone();
+        let origin = testkit::serve(move |request| {
+            let body: String = match request.url() {
+                "/" => {
+                    // cancelled while the seed is being fetched
+                    _ = cancel.transition(JobState::Cancelled);
+                    page("Home", &["/a", "/b"])
+                }
+                "/a" | "/b" => page("Leaf", &[]),
+                _ => return tiny_http::Response::from_string("").with_status_code(404).boxed()
+            };
+            tiny_http::Response::from_string(body)
+                .with_header(tiny_http::Header::from_bytes("Content-Type", "text/html").unwrap())
+                .boxed()
+        });
+        let (ctx, resumed) = test_crawler("cancel", &origin, CrawlerOptions {
+            job_id: Some(4),
+            ..CrawlerOptions::default()
+        });
+
+        let cancelled = Crawler::with_control(resumed.index.clone(), resumed.options.clone(), control);
+        cancelled.start_crawling().await.unwrap();
+        assert_eq!(cancelled.control().state(), JobState::Cancelled);
+        assert_eq!(cancelled.control().pages_fetched.load(Ordering::Relaxed), 1);
+        assert!(Frontier::new(ctx.rocks_con.clone(), Some(4), 2).restore().unwrap());
+
+        // restarted by id, it picks up the links the seed had and doesn't fetch the seed again
+        resumed.start_crawling().await.unwrap();
+        assert_eq!(resumed.control().state(), JobState::Done);
+        assert_eq!(resumed.control().pages_fetched.load(Ordering::Relaxed), 2);
+        assert!(IndexReader::new(&ctx).doc_id(&format!("{}/b", origin)).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_crawl_queues_sitemap_urls() {
+        let origin = serve_site(vec![
+            ("/robots.txt", "User-agent: *\nDisallow: /private\nSitemap: /sitemaps/index.xml".to_string()),
+            ("/sitemaps/index.xml", "<sitemapindex><sitemap><loc>/sitemaps/pages.xml</loc></sitemap><sitemap><loc>/private/pages.xml</loc></sitemap></sitemapindex>".to_string()),
+            ("/sitemaps/pages.xml", "<urlset><url><loc>/orphan</loc><priority>0.8</priority></url><url><loc>/private</loc></url></urlset>".to_string()),
+            // disallowed by robots.txt, so never fetched
+            ("/private/pages.xml", "<urlset><url><loc>/hidden</loc></url></urlset>".to_string()),
+            // not listed in robots.txt, but still looked for
+            ("/sitemap.xml", "<urlset><url><loc>/unlisted</loc></url></urlset>".to_string()),
+            ("/", page("Home", &[])),
+            ("/orphan", page("Orphan", &[])),
+            ("/unlisted", page("Unlisted", &[])),
+            ("/private", page("Private", &[])),
+            ("/hidden", page("Hidden", &[]))
+        ]);
+
+        let (ctx, crawler) = test_crawler("sitemap", &origin, CrawlerOptions::default());
+        crawler.start_crawling().await.unwrap();
+
+        let reader = IndexReader::new(&ctx);
+        assert!(reader.doc_id(&format!("{}/orphan", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/unlisted", origin)).unwrap().is_some());
+        assert!(reader.doc_id(&format!("{}/private", origin)).unwrap().is_none());
+        assert!(reader.doc_id(&format!("{}/hidden", origin)).unwrap().is_none());
+        assert_eq!(crawler.control().pages_fetched.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_recrawl_skips_unchanged_pages() {
+        let origin = serve_site(vec![
+            ("/", page("Home", &["/a"])),
+            ("/a", page("A", &[]))
+        ]);
+        let (ctx, first) = test_crawler("recrawl", &origin, CrawlerOptions::default());
+        first.start_crawling().await.unwrap();
+        assert_eq!(first.control().pages_unchanged.load(Ordering::Relaxed), 0);
+
+        // the seed answers 304 and /a is still found through its links in the link graph
+        let second = Crawler::new(first.index.clone(), first.options.clone());
+        second.start_crawling().await.unwrap();
+        assert_eq!(second.control().pages_fetched.load(Ordering::Relaxed), 0);
+        assert_eq!(second.control().pages_unchanged.load(Ordering::Relaxed), 2);
+
+        let meta = FetchMetaStore::new(ctx.rocks_con.clone()).get(&format!("{}/a", origin)).unwrap().unwrap();
+        assert_eq!((meta.fetches, meta.changes), (2, 0));
+        assert!(meta.etag.is_some());
+        assert_eq!(IndexReader::new(&ctx).doc_ids().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_due_pages_are_queued_with_their_known_inlinks() {
+        let (_ctx, crawler) = test_crawler("due-inlinks", "https://example.com", CrawlerOptions::default());
+
+        let meta = FetchMeta { last_fetched: 1, interval: 1, ..FetchMeta::default() };
+        crawler.fetch_meta.put("https://example.com/a", &meta).unwrap();
+        crawler.graph.set_outlinks("https://example.com/", &["https://example.com/a".to_string()]).unwrap();
+        crawler.graph.set_outlinks("https://example.com/b", &["https://example.com/a".to_string()]).unwrap();
+
+        crawler.queue_due_pages();
+        let entry = crawler.frontier.pop(|_| true).unwrap();
+        assert_eq!((entry.url.as_str(), entry.inlinks), ("https://example.com/a", 2));
+    }
+
+    #[tokio::test]
+    async fn test_failed_fetches_are_recorded() {
+        let origin = serve_site(vec![
+            ("/", page("Home", &["/missing", "/a"])),
+            ("/a", page("A", &[]))
+        ]);
+
+        let (ctx, crawler) = test_crawler("failures", &origin, CrawlerOptions {
+            job_id: Some(5),
+            ..CrawlerOptions::default()
+        });
+        crawler.start_crawling().await.unwrap();
+        assert_eq!(crawler.control().pages_failed.load(Ordering::Relaxed), 1);
+
+        let failures = FailureLog::new(ctx.rocks_con.clone()).list(Some(5)).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].url, format!("{}/missing", origin));
+        assert_eq!(failures[0].kind, "status");
+        assert_eq!(failures[0].message, format!("{}/missing answered with status 404", origin));
+
+        // the dead page waits before it's tried again
+        let store = FetchMetaStore::new(ctx.rocks_con.clone());
+        let meta = store.get(&format!("{}/missing", origin)).unwrap().unwrap();
+        assert_eq!((meta.fetches, meta.interval), (0, MIN_RECRAWL_INTERVAL.as_secs()));
+        assert!(!store.due(now_secs()).unwrap().contains(&format!("{}/missing", origin)));
+    }
+}
@@ -1,3 +1,179 @@
+use std::{fmt, sync::Arc};
+
+use crate::JobState;
+
+/// Why fetching or storing part of a crawl failed.
+#[derive(Debug, Clone)]
 pub enum CrawlerError {
-    
-}
\ No newline at end of file
+    /// Connecting, sending the request or reading the response failed.
+    Network {
+        url: String,
+        source: Arc<reqwest::Error>
+    },
+
+    /// The server answered with something other than a 2xx or 304.
+    Status {
+        url: String,
+        status: u16
+    },
+
+    /// Redirects went around in circles or past the limit.
+    RedirectLoop {
+        url: String
+    },
+
+    RobotsDisallowed {
+        url: String
+    },
+
+    /// The response isn't an HTML page.
+    NotHtml {
+        url: String,
+        content_type: String
+    },
+
+    /// The body couldn't be decoded or parsed.
+    Parse {
+        url: String,
+        message: String
+    },
+
+    /// The body is larger than `limit` bytes.
+    TooLarge {
+        url: String,
+        limit: u64
+    },
+
+    /// No response in time.
+    Timeout {
+        url: String
+    },
+
+    /// Reading or writing crawl state failed; `context` says which.
+    Storage {
+        context: String,
+        message: String
+    }
+}
+
+impl CrawlerError {
+    /// Sorts a reqwest error into a timeout, a redirect loop or a network error.
+    pub(crate) fn request(url: impl Into<String>, err: reqwest::Error) -> Self {
+        let url: String = url.into();
+        if err.is_timeout() {
+            CrawlerError::Timeout { url }
+        } else if err.is_redirect() {
+            CrawlerError::RedirectLoop { url }
+        } else {
+            CrawlerError::Network { url, source: Arc::new(err) }
+        }
+    }
+
+    pub(crate) fn storage(context: impl Into<String>, err: impl fmt::Display) -> Self {
+        CrawlerError::Storage { context: context.into(), message: err.to_string() }
+    }
+
+    /// Short name of the variant, e.g. `status` or `timeout`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CrawlerError::Network { .. } => "network",
+            CrawlerError::Status { .. } => "status",
+            CrawlerError::RedirectLoop { .. } => "redirect_loop",
+            CrawlerError::RobotsDisallowed { .. } => "robots_disallowed",
+            CrawlerError::NotHtml { .. } => "not_html",
+            CrawlerError::Parse { .. } => "parse",
+            CrawlerError::TooLarge { .. } => "too_large",
+            CrawlerError::Timeout { .. } => "timeout",
+            CrawlerError::Storage { .. } => "storage"
+        }
+    }
+
+    /// The same error about `url`, e.g. for the pages a robots.txt that
+    /// couldn't be fetched keeps from being crawled.
+    pub(crate) fn for_url(&self, url: impl Into<String>) -> Self {
+        let mut err: CrawlerError = self.clone();
+        match &mut err {
+            CrawlerError::Network { url: failed, .. }
+                | CrawlerError::Status { url: failed, .. }
+                | CrawlerError::RedirectLoop { url: failed }
+                | CrawlerError::RobotsDisallowed { url: failed }
+                | CrawlerError::NotHtml { url: failed, .. }
+                | CrawlerError::Parse { url: failed, .. }
+                | CrawlerError::TooLarge { url: failed, .. }
+                | CrawlerError::Timeout { url: failed } => *failed = url.into(),
+            CrawlerError::Storage { .. } => {}
+        }
+        err
+    }
+
+    /// The URL that failed; `None` for storage errors.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            CrawlerError::Network { url, .. }
+                | CrawlerError::Status { url, .. }
+                | CrawlerError::RedirectLoop { url }
+                | CrawlerError::RobotsDisallowed { url }
+                | CrawlerError::NotHtml { url, .. }
+                | CrawlerError::Parse { url, .. }
+                | CrawlerError::TooLarge { url, .. }
+                | CrawlerError::Timeout { url } => Some(url),
+            CrawlerError::Storage { .. } => None
+        }
+    }
+}
+
+impl fmt::Display for CrawlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrawlerError::Network { url, source } => write!(f, "failed to fetch {}: {}", url, source),
+            CrawlerError::Status { url, status } => write!(f, "{} answered with status {}", url, status),
+            CrawlerError::RedirectLoop { url } => write!(f, "too many redirects from {}", url),
+            CrawlerError::RobotsDisallowed { url } => write!(f, "robots.txt disallows {}", url),
+            CrawlerError::NotHtml { url, content_type } => write!(f, "{} isn't HTML but {}", url, content_type),
+            CrawlerError::Parse { url, message } => write!(f, "failed to parse {}: {}", url, message),
+            CrawlerError::TooLarge { url, limit } => write!(f, "{} is larger than {} bytes", url, limit),
+            CrawlerError::Timeout { url } => write!(f, "{} timed out", url),
+            CrawlerError::Storage { context, message } => write!(f, "failed to store {}: {}", context, message)
+        }
+    }
+}
+
+impl std::error::Error for CrawlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrawlerError::Network { source, .. } => Some(source.as_ref()),
+            _ => None
+        }
+    }
+}
+
+/// Failure of a crawl job control request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    NotFound(u64),
+
+    /// A job with this id is already queued, running or paused.
+    AlreadyRunning(u64),
+
+    /// The job can't go from its current state to the requested one,
+    /// e.g. resuming a job that isn't paused.
+    InvalidTransition {
+        id: u64,
+        from: JobState,
+        to: JobState
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::NotFound(id) => write!(f, "no crawl job with id {}", id),
+            JobError::AlreadyRunning(id) => write!(f, "crawl job {} is already running", id),
+            JobError::InvalidTransition { id, from, to } => {
+                write!(f, "crawl job {} can't go from {} to {}", id, from.name(), to.name())
+            }
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
@@ -0,0 +1,142 @@
+//! URLs crawls failed to fetch, kept so they can be looked at after the
+//! crawl is over.
+//!
+//! Stored as JSON `CrawlFailure` under `failure:{job:020} {url}`, job 0
+//! for crawls without an id, so every job keeps its own record. A later
+//! successful fetch of the URL in the same job removes it.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::CrawlerError, now_secs};
+
+const FAILURE_PREFIX: &str = "failure:";
+
+fn job_prefix(job_id: Option<u64>) -> String {
+    format!("failure:{:020} ", job_id.unwrap_or_default())
+}
+
+fn failure_key(job_id: Option<u64>, url: &str) -> String {
+    format!("{}{}", job_prefix(job_id), url)
+}
+
+/// Last failure of a URL in a crawl job.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrawlFailure {
+    pub url: String,
+
+    /// `CrawlerError::kind` of the error.
+    pub kind: String,
+    pub message: String,
+
+    /// Crawl job the failure happened in, if it had an id.
+    pub job_id: Option<u64>,
+
+    /// Seconds since 1970-01-01.
+    pub failed_at: u64,
+
+    /// Failures in a row within the job, this one included.
+    pub attempts: u32
+}
+
+#[derive(Debug, Clone)]
+pub struct FailureLog {
+    db: Arc<rocksdb::DB>
+}
+
+impl FailureLog {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self { db }
+    }
+
+    /// Records `err` against its URL. Errors without one aren't recorded.
+    pub fn record(&self, job_id: Option<u64>, err: &CrawlerError) -> Result<(), CrawlerError> {
+        let Some(url) = err.url() else {
+            return Ok(());
+        };
+        let attempts: u32 = self.get(job_id, url)?.map_or(0, |failure| failure.attempts);
+
+        let failure = CrawlFailure {
+            url: url.to_string(),
+            kind: err.kind().to_string(),
+            message: err.to_string(),
+            job_id,
+            failed_at: now_secs(),
+            attempts: attempts + 1
+        };
+        let key: String = failure_key(job_id, url);
+        let value: Vec<u8> = serde_json::to_vec(&failure).map_err(|err| CrawlerError::storage(&key, err))?;
+        self.db.put(&key, value).map_err(|err| CrawlerError::storage(&key, err))
+    }
+
+    /// Forgets the failures of `url` in the job, e.g. after it was
+    /// fetched fine.
+    pub fn clear(&self, job_id: Option<u64>, url: &str) -> Result<(), CrawlerError> {
+        let key: String = failure_key(job_id, url);
+        self.db.delete(&key).map_err(|err| CrawlerError::storage(&key, err))
+    }
+
+    pub fn get(&self, job_id: Option<u64>, url: &str) -> Result<Option<CrawlFailure>, CrawlerError> {
+        let key: String = failure_key(job_id, url);
+        let value: Option<Vec<u8>> = self.db.get(&key).map_err(|err| CrawlerError::storage(&key, err))?;
+        value.map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .map_err(|err| CrawlerError::storage(&key, err))
+    }
+
+    /// Failures of the job `job_id`, by URL, or of every crawl when `None`.
+    pub fn list(&self, job_id: Option<u64>) -> Result<Vec<CrawlFailure>, CrawlerError> {
+        let prefix: String = match job_id {
+            Some(_) => job_prefix(job_id),
+            None => FAILURE_PREFIX.to_string()
+        };
+        let mut failures: Vec<CrawlFailure> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, value) = key_value.map_err(|err| CrawlerError::storage("failures", err))?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            failures.push(serde_json::from_slice(&value).map_err(|err| CrawlerError::storage("failures", err))?);
+        }
+        Ok(failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testkit::TestCtx;
+
+    use super::*;
+
+    #[test]
+    fn test_record_list_and_clear() {
+        let ctx = TestCtx::new("failures");
+        let log = FailureLog::new(ctx.rocks_con.clone());
+
+        let not_found = CrawlerError::Status { url: "https://a.com/gone".to_string(), status: 404 };
+        log.record(Some(1), &not_found).unwrap();
+        log.record(Some(1), &not_found).unwrap();
+        log.record(Some(2), &CrawlerError::Timeout { url: "https://b.com/slow".to_string() }).unwrap();
+        log.record(Some(2), &CrawlerError::storage("checkpoint", "disk full")).unwrap();
+
+        let failures = log.list(Some(1)).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].kind, "status");
+        assert_eq!(failures[0].message, "https://a.com/gone answered with status 404");
+        assert_eq!(failures[0].attempts, 2);
+        assert_eq!(log.list(None).unwrap().len(), 2);
+
+        // a later job keeps a record of its own
+        log.record(Some(3), &not_found).unwrap();
+        assert_eq!(log.get(Some(3), "https://a.com/gone").unwrap().unwrap().attempts, 1);
+        assert_eq!(log.list(Some(1)).unwrap()[0].attempts, 2);
+
+        log.clear(Some(1), "https://a.com/gone").unwrap();
+        assert_eq!(log.get(Some(1), "https://a.com/gone").unwrap(), None);
+        assert!(log.list(Some(1)).unwrap().is_empty());
+        assert_eq!(log.list(Some(3)).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,265 @@
+//! HTTP fetching: the client a crawl shares, retries of failed requests
+//! and limits on what gets downloaded.
+
+use std::time::Duration;
+
+use reqwest::{header, StatusCode};
+
+use crate::{days_since_epoch, errors::CrawlerError, now_secs};
+
+/// Content types fetched pages may have. A response without one is
+/// assumed to be HTML.
+const HTML_CONTENT_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+
+/// Timeouts, redirects, retries and size limit of a crawl's requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FetchOptions {
+    /// Time to establish a connection.
+    pub connect_timeout: Duration,
+
+    /// Time to wait for each read of the response.
+    pub read_timeout: Duration,
+
+    /// Redirects followed per request before giving up.
+    pub max_redirects: usize,
+
+    /// Retries of requests answered with a 5xx or 429.
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled for every further one.
+    pub retry_backoff: Duration,
+
+    /// Longest delay before a retry, `Retry-After` included.
+    pub max_retry_delay: Duration,
+
+    /// Larger pages are refused.
+    pub max_body_bytes: u64
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            max_redirects: 5,
+            max_retries: 3,
+            retry_backoff: Duration::from_secs(1),
+            max_retry_delay: Duration::from_secs(60),
+            max_body_bytes: 10 * 1024 * 1024
+        }
+    }
+}
+
+impl FetchOptions {
+    /// Client that sends every request of a crawl, robots.txt and
+    /// sitemaps included.
+    pub fn client(&self, user_agent: &str) -> reqwest::Client {
+        reqwest::Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .redirect(reqwest::redirect::Policy::limited(self.max_redirects))
+            .build()
+            .expect("Failed to create client")
+    }
+
+    /// Delay before retry number `attempt`, counted from 0: what the
+    /// server asked for, else exponential backoff.
+    pub fn retry_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff: Duration = self.retry_backoff.saturating_mul(2u32.saturating_pow(attempt));
+        retry_after.unwrap_or(backoff).min(self.max_retry_delay)
+    }
+}
+
+/// Sends `request`, retrying it while the server answers with a 5xx or
+/// 429 and retries are left. The last response is returned either way.
+pub async fn send_with_retries(request: reqwest::RequestBuilder, url: &str, options: &FetchOptions) -> Result<reqwest::Response, CrawlerError> {
+    let mut attempt: u32 = 0;
+    loop {
+        // bodies of GET requests are never streams, so they always clone
+        let Some(retry) = request.try_clone() else {
+            return request.send().await.map_err(|err| CrawlerError::request(url, err));
+        };
+        let resp = retry.send().await.map_err(|err| CrawlerError::request(url, err))?;
+
+        let status: StatusCode = resp.status();
+        let retryable: bool = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+        if !retryable || attempt >= options.max_retries {
+            return Ok(resp);
+        }
+
+        let retry_after: Option<Duration> = resp.headers().get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, now_secs()));
+        tokio::time::sleep(options.retry_delay(attempt, retry_after)).await;
+        attempt += 1;
+    }
+}
+
+/// `Retry-After` as a delay: either seconds or an HTTP date, which is
+/// measured from `now` (seconds since 1970-01-01).
+pub fn parse_retry_after(value: &str, now: u64) -> Option<Duration> {
+    let value: &str = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+    let [_, day, month, year, time, "GMT"] = value.split_whitespace().collect::<Vec<&str>>()[..] else {
+        return None;
+    };
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month: usize = MONTHS.iter().position(|name| *name == month)? + 1;
+    let days: i64 = days_since_epoch(&format!("{}-{:02}-{}", year, month, day))?;
+
+    let mut clock = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (clock.next()??, clock.next()??, clock.next()??);
+    let at: i64 = days * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    Some(Duration::from_secs(at.saturating_sub(now as i64).max(0) as u64))
+}
+
+/// Refuses responses that declare a content type other than HTML, before
+/// their body is downloaded.
+pub fn check_html(resp: &reqwest::Response, url: &str) -> Result<(), CrawlerError> {
+    let Some(content_type) = resp.headers().get(header::CONTENT_TYPE) else {
+        return Ok(());
+    };
+    let content_type: String = String::from_utf8_lossy(content_type.as_bytes()).into_owned();
+    let mime: String = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    if HTML_CONTENT_TYPES.contains(&mime.as_str()) {
+        Ok(())
+    } else {
+        Err(CrawlerError::NotHtml { url: url.to_string(), content_type })
+    }
+}
+
+/// Decodes `body` with the charset its `Content-Type` names, like
+/// `reqwest::Response::text` does: UTF-8 when there's none or it's
+/// unknown, and a byte order mark wins over both.
+pub fn decode_body(body: &[u8], content_type: Option<&str>) -> String {
+    let encoding: &'static encoding_rs::Encoding = content_type
+        .and_then(|content_type| {
+            content_type.split(';')
+                .skip(1)
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+                .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        })
+        .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+
+    encoding.decode(body).0.into_owned()
+}
+
+/// Downloads the body of `resp`, giving up as soon as it's known to be
+/// larger than `limit` bytes.
+pub async fn read_body(mut resp: reqwest::Response, url: &str, limit: u64) -> Result<Vec<u8>, CrawlerError> {
+    let too_large = || CrawlerError::TooLarge { url: url.to_string(), limit };
+    if resp.content_length().is_some_and(|length| length > limit) {
+        return Err(too_large());
+    }
+
+    let mut body: Vec<u8> = vec![];
+    while let Some(chunk) = resp.chunk().await.map_err(|err| CrawlerError::request(url, err))? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc
+    };
+
+    use super::*;
+
+    /// Status, headers and body of a response.
+    type Reply = (u16, Vec<(&'static str, &'static str)>, String);
+
+    /// Answers the n-th request with the n-th of `responses`, the last
+    /// one over and over once they run out.
+    /// Returns the URL and the number of requests served so far.
+    fn serve(responses: Vec<Reply>) -> (String, Arc<AtomicUsize>) {
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        let origin = testkit::serve(move |_| {
+            let n: usize = counter.fetch_add(1, Ordering::SeqCst).min(responses.len() - 1);
+            let (status, headers, body) = &responses[n];
+            let mut response = tiny_http::Response::from_string(body.clone()).with_status_code(*status);
+            for (name, value) in headers {
+                response.add_header(tiny_http::Header::from_bytes(*name, *value).unwrap());
+            }
+            response.boxed()
+        });
+        (format!("{}/", origin), served)
+    }
+
+    #[test]
+    fn test_decode_body_honors_charset() {
+        assert_eq!(decode_body(b"caf\xe9", Some("text/html; charset=ISO-8859-1")), "café");
+        assert_eq!(decode_body(b"caf\xe9", Some(r#"text/html;Charset="windows-1252""#)), "café");
+        assert_eq!(decode_body("café".as_bytes(), Some("text/html")), "café");
+        assert_eq!(decode_body("café".as_bytes(), Some("text/html; charset=nonsense")), "café");
+        assert_eq!(decode_body(b"caf\xe9", None), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let options = FetchOptions::default();
+        assert_eq!(options.retry_delay(0, None), Duration::from_secs(1));
+        assert_eq!(options.retry_delay(3, None), Duration::from_secs(8));
+        assert_eq!(options.retry_delay(30, None), options.max_retry_delay);
+        assert_eq!(options.retry_delay(3, Some(Duration::from_secs(2))), Duration::from_secs(2));
+        assert_eq!(options.retry_delay(0, Some(Duration::from_secs(3600))), options.max_retry_delay);
+
+        assert_eq!(parse_retry_after(" 120 ", 0), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", 784_111_747), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", 900_000_000), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        let (url, served) = serve(vec![
+            (503, vec![], String::new()),
+            (429, vec![("Retry-After", "0")], String::new()),
+            (200, vec![], "ok".to_string())
+        ]);
+        let options = FetchOptions { retry_backoff: Duration::from_millis(1), ..FetchOptions::default() };
+        let client = options.client("test");
+
+        let resp = send_with_retries(client.get(&url), &url, &options).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+
+        // out of retries, the last answer is returned as is
+        let (url, served) = serve(vec![(500, vec![], String::new())]);
+        let options = FetchOptions { max_retries: 2, ..options };
+        let resp = send_with_retries(client.get(&url), &url, &options).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_non_html_and_large_bodies() {
+        let client = FetchOptions::default().client("test");
+
+        let (url, _) = serve(vec![(200, vec![("Content-Type", "image/png")], "png".to_string())]);
+        let resp = client.get(&url).send().await.unwrap();
+        assert_eq!(check_html(&resp, &url).unwrap_err().kind(), "not_html");
+
+        let (url, _) = serve(vec![(200, vec![("Content-Type", "text/html; charset=utf-8")], "x".repeat(100))]);
+        let resp = client.get(&url).send().await.unwrap();
+        assert!(check_html(&resp, &url).is_ok());
+        assert_eq!(read_body(resp, &url, 99).await.unwrap_err().kind(), "too_large");
+
+        let resp = client.get(&url).send().await.unwrap();
+        assert_eq!(read_body(resp, &url, 100).await.unwrap().len(), 100);
+    }
+}
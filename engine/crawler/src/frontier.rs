@@ -0,0 +1,603 @@
+//! Links waiting to be crawled and the pages already seen, checkpointed
+//! to RocksDB so a crawl job can pick up where it stopped.
+//!
+//! Key layout, next to the index's keys:
+//! - `crawl:{job:020}:queued:{visit key}`: JSON `FrontierEntry`, one per
+//!   page waiting or in flight
+//! - `crawl:{job:020}:seen:{visit key}`: empty, one per crawled page
+//! - `crawl:{job:020}:done`: empty, left once the job finished so its id
+//!   isn't handed out again
+
+use std::{
+    cmp::Ordering,
+    collections::{
+        BinaryHeap,
+        HashMap,
+        HashSet
+    },
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::CrawlerError, now_secs, visit_key};
+
+const CRAWL_PREFIX: &str = "crawl:";
+
+/// A key and its value, as read from RocksDB.
+type KeyValue = (Box<[u8]>, Box<[u8]>);
+
+/// How much each level below the seed lowers a link's priority.
+const DEPTH_WEIGHT: f64 = 1.0;
+
+/// Weight of the (log) number of pages linking to a URL.
+const INLINK_WEIGHT: f64 = 0.5;
+
+/// Weight of `FrontierEntry::value`.
+const VALUE_WEIGHT: f64 = 2.0;
+
+/// Weight of the (log) number of pages already taken from a host, so one
+/// big site doesn't crowd out the others.
+const HOST_WEIGHT: f64 = 0.5;
+
+/// File extensions that rarely lead to an HTML page.
+const NON_HTML_EXTENSIONS: [&str; 12] = ["pdf", "zip", "gz", "tar", "png", "jpg", "jpeg", "gif", "svg", "mp3", "mp4", "exe"];
+
+fn job_prefix(job_id: u64) -> String {
+    format!("crawl:{:020}:", job_id)
+}
+
+fn queued_prefix(job_id: u64) -> String {
+    format!("crawl:{:020}:queued:", job_id)
+}
+
+fn done_key(job_id: u64) -> String {
+    format!("crawl:{:020}:done", job_id)
+}
+
+fn seen_prefix(job_id: u64) -> String {
+    format!("crawl:{:020}:seen:", job_id)
+}
+
+fn host_of(url: &str) -> String {
+    url::Url::parse(url).ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+        .unwrap_or_default()
+}
+
+/// Guess of how worth crawling `url` is, in `[0, 1]`, from its shape
+/// alone: pages close to the site root, without query strings and that
+/// look like HTML score higher.
+pub fn estimate_value(url: &str) -> f64 {
+    let Ok(url) = url::Url::parse(url) else {
+        return 0.0;
+    };
+
+    let segments: Vec<&str> = url.path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+        .unwrap_or_default();
+    let mut value: f64 = 1.0 - 0.1 * segments.len().saturating_sub(1) as f64;
+
+    if url.query().is_some() {
+        value -= 0.2;
+    }
+    let extension: Option<String> = segments.last()
+        .and_then(|segment| segment.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    if extension.is_some_and(|extension| NON_HTML_EXTENSIONS.contains(&extension.as_str())) {
+        value -= 0.5;
+    }
+    value.clamp(0.0, 1.0)
+}
+
+/// A link waiting in the frontier.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrontierEntry {
+    pub url: String,
+
+    /// Links followed from the seed to get here.
+    pub depth: usize,
+
+    /// Estimated worth of the page in `[0, 1]`, see `estimate_value`.
+    pub value: f64,
+
+    /// Crawled pages found linking here so far.
+    pub inlinks: usize,
+
+    /// When the site says the page last changed, from its sitemap.
+    #[serde(default)]
+    pub lastmod: Option<String>,
+
+    /// Page or sitemap the link was found on, the one it's least deep
+    /// from when there are several. `None` for seeds.
+    #[serde(default)]
+    pub referrer: Option<String>,
+
+    /// When the link was first found, in seconds since 1970-01-01.
+    #[serde(default)]
+    pub discovered_at: u64
+}
+
+impl FrontierEntry {
+    pub fn new(url: String, depth: usize) -> Self {
+        let value: f64 = estimate_value(&url);
+        Self {
+            url,
+            depth,
+            value,
+            inlinks: 0,
+            lastmod: None,
+            referrer: None,
+            discovered_at: now_secs()
+        }
+    }
+
+    /// Priority within the frontier, before host fairness.
+    pub fn score(&self) -> f64 {
+        VALUE_WEIGHT * self.value + INLINK_WEIGHT * (self.inlinks as f64).ln_1p() - DEPTH_WEIGHT * self.depth as f64
+    }
+}
+
+/// Heap item of a queued entry. An entry whose score changed is pushed
+/// again, the outdated item is skipped when it comes up.
+#[derive(Debug)]
+struct Scored {
+    score: f64,
+
+    /// Push order, so equal scores come out first in, first out.
+    seq: u64,
+    key: String,
+    inlinks: usize
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Debug, Default)]
+struct HostQueue {
+    heap: BinaryHeap<Scored>,
+
+    /// Entries taken and not completed yet.
+    in_flight: usize,
+
+    /// Entries ever taken.
+    taken: usize
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Queued entries by visit key.
+    queued: HashMap<String, FrontierEntry>,
+    hosts: HashMap<String, HostQueue>,
+    seen: HashSet<String>,
+
+    /// Taken entries by visit key, until `complete`d. Checkpoints keep
+    /// them in the frontier, so a crawl stopped mid-fetch doesn't lose them.
+    in_flight: HashMap<String, FrontierEntry>,
+
+    /// Seen keys not written by a checkpoint yet.
+    unsaved: Vec<String>,
+
+    /// Visit keys whose entry was queued, changed or dropped since the
+    /// last checkpoint.
+    changed: HashSet<String>,
+    next_seq: u64
+}
+
+impl State {
+    fn enqueue(&mut self, key: String, entry: FrontierEntry) {
+        let scored = Scored {
+            score: entry.score(),
+            seq: self.next_seq,
+            key: key.clone(),
+            inlinks: entry.inlinks
+        };
+        self.next_seq += 1;
+        self.hosts.entry(host_of(&entry.url)).or_default().heap.push(scored);
+        self.changed.insert(key.clone());
+        self.queued.insert(key, entry);
+    }
+
+    /// Drops the outdated items at the top of `host`'s heap and returns
+    /// the score of the first live one.
+    fn peek(&mut self, host: &str) -> Option<f64> {
+        let queue: &mut HostQueue = self.hosts.get_mut(host)?;
+        while let Some(top) = queue.heap.peek() {
+            match self.queued.get(&top.key) {
+                Some(entry) if entry.inlinks == top.inlinks => return Some(top.score),
+                _ => { queue.heap.pop(); }
+            }
+        }
+        None
+    }
+}
+
+/// Priority queue of the links to crawl. Entries come out by score, from
+/// hosts that have a request slot free, each page once.
+#[derive(Debug)]
+pub struct Frontier {
+    db: Arc<rocksdb::DB>,
+
+    /// Where the crawl is checkpointed; `None` keeps it in memory only.
+    job_id: Option<u64>,
+
+    /// Requests in flight to a single host, see `PolitenessOptions`.
+    max_per_host: usize,
+    state: Mutex<State>
+}
+
+impl Frontier {
+    pub fn new(db: Arc<rocksdb::DB>, job_id: Option<u64>, max_per_host: usize) -> Self {
+        Self {
+            db,
+            job_id,
+            max_per_host: max_per_host.max(1),
+            state: Mutex::new(State::default())
+        }
+    }
+
+    /// Replaces the frontier with the job's last checkpoint. Returns
+    /// `false` when the job has none.
+    pub fn restore(&self) -> Result<bool, CrawlerError> {
+        let Some(job_id) = self.job_id else {
+            return Ok(false);
+        };
+
+        let mut state = State::default();
+        let mut queued: Vec<FrontierEntry> = vec![];
+        let seen_prefix: String = seen_prefix(job_id);
+        let queued_prefix: String = queued_prefix(job_id);
+        for (key, value) in self.scan(&job_prefix(job_id))? {
+            if let Some(visit_key) = key.strip_prefix(seen_prefix.as_bytes()) {
+                state.seen.insert(String::from_utf8_lossy(visit_key).into_owned());
+            } else if key.starts_with(queued_prefix.as_bytes()) {
+                let entry: FrontierEntry = serde_json::from_slice(&value)
+                    .map_err(|err| CrawlerError::storage(String::from_utf8_lossy(&key), err))?;
+                queued.push(entry);
+            }
+        }
+        if state.seen.is_empty() && queued.is_empty() {
+            return Ok(false);
+        }
+
+        for entry in queued {
+            let key: String = visit_key(&entry.url).unwrap_or_else(|| entry.url.clone());
+            if !state.seen.contains(&key) {
+                state.enqueue(key, entry);
+            }
+        }
+        state.changed.clear();
+        *self.state.lock().unwrap() = state;
+        Ok(true)
+    }
+
+    /// Writes the entries queued, changed or dropped and the pages
+    /// crawled since the last checkpoint, so a crawl with the same job id
+    /// resumes from here.
+    pub fn checkpoint(&self) -> Result<(), CrawlerError> {
+        let Some(job_id) = self.job_id else {
+            return Ok(());
+        };
+
+        let mut batch = rocksdb::WriteBatch::default();
+        let (changed, unsaved) = {
+            let mut state = self.state.lock().unwrap();
+            let changed: HashSet<String> = std::mem::take(&mut state.changed);
+            for key in &changed {
+                let queued_key: String = format!("{}{}", queued_prefix(job_id), key);
+                match state.queued.get(key).or_else(|| state.in_flight.get(key)) {
+                    Some(entry) => {
+                        let value: Vec<u8> = serde_json::to_vec(entry).map_err(|err| CrawlerError::storage(&queued_key, err))?;
+                        batch.put(queued_key, value);
+                    }
+                    None => batch.delete(queued_key)
+                }
+            }
+            (changed, std::mem::take(&mut state.unsaved))
+        };
+        for key in &unsaved {
+            batch.put(format!("{}{}", seen_prefix(job_id), key), b"");
+        }
+
+        if let Err(err) = self.db.write(batch) {
+            // keep them for the next checkpoint
+            let mut state = self.state.lock().unwrap();
+            state.changed.extend(changed);
+            state.unsaved.extend(unsaved);
+            return Err(CrawlerError::storage(job_prefix(job_id), err));
+        }
+        Ok(())
+    }
+
+    /// Deletes the job's checkpoint once it's done, leaving a marker so
+    /// the id isn't reused. A cancelled job keeps its checkpoint.
+    pub fn finish(&self) -> Result<(), CrawlerError> {
+        let Some(job_id) = self.job_id else {
+            return Ok(());
+        };
+
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, _) in self.scan(&job_prefix(job_id))? {
+            batch.delete(key);
+        }
+        batch.put(done_key(job_id), b"");
+        self.db.write(batch).map_err(|err| CrawlerError::storage(job_prefix(job_id), err))
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KeyValue>, CrawlerError> {
+        let mut entries: Vec<KeyValue> = vec![];
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, value) = key_value.map_err(|err| CrawlerError::storage(prefix, err))?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    /// Queues `entry` unless its page was seen. A link to an already
+    /// queued page counts as one more inlink to it instead.
+    pub fn push(&self, entry: FrontierEntry) {
+        let key: String = visit_key(&entry.url).unwrap_or_else(|| entry.url.clone());
+        let mut state = self.state.lock().unwrap();
+        if state.seen.contains(&key) {
+            return;
+        }
+
+        let entry: FrontierEntry = match state.queued.get(&key) {
+            Some(queued) => FrontierEntry {
+                depth: queued.depth.min(entry.depth),
+                value: queued.value.max(entry.value),
+                inlinks: queued.inlinks + entry.inlinks.max(1),
+                lastmod: entry.lastmod.or_else(|| queued.lastmod.clone()),
+                referrer: if entry.depth < queued.depth { entry.referrer } else { queued.referrer.clone() },
+                ..queued.clone()
+            },
+            None => entry
+        };
+        state.enqueue(key, entry);
+    }
+
+    /// Takes the best entry from a host with a free request slot and marks
+    /// its page seen. Entries `admit` rejects are dropped. `None` when
+    /// nothing is queued or every host with queued entries is busy.
+    pub fn pop(&self, admit: impl Fn(&str) -> bool) -> Option<FrontierEntry> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let hosts: Vec<String> = state.hosts.keys().cloned().collect();
+            let mut best: Option<(f64, String)> = None;
+            for host in hosts {
+                let Some(score) = state.peek(&host) else {
+                    continue;
+                };
+                let queue: &HostQueue = &state.hosts[&host];
+                if queue.in_flight >= self.max_per_host {
+                    continue;
+                }
+
+                let score: f64 = score - HOST_WEIGHT * (queue.taken as f64).ln_1p();
+                if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+                    best = Some((score, host));
+                }
+            }
+
+            let (_, host) = best?;
+            let scored: Scored = state.hosts.get_mut(&host)?.heap.pop()?;
+            let entry: FrontierEntry = state.queued.remove(&scored.key)?;
+            if !admit(&entry.url) {
+                state.changed.insert(scored.key);
+                continue;
+            }
+
+            let queue: &mut HostQueue = state.hosts.get_mut(&host)?;
+            queue.in_flight += 1;
+            queue.taken += 1;
+            state.seen.insert(scored.key.clone());
+            state.in_flight.insert(scored.key, entry.clone());
+            return Some(entry);
+        }
+    }
+
+    /// Frees the host slot `entry` took and records its page as crawled.
+    pub fn complete(&self, entry: &FrontierEntry) {
+        let key: String = visit_key(&entry.url).unwrap_or_else(|| entry.url.clone());
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight.remove(&key).is_none() {
+            return;
+        }
+        if let Some(queue) = state.hosts.get_mut(&host_of(&entry.url)) {
+            queue.in_flight = queue.in_flight.saturating_sub(1);
+        }
+        state.changed.insert(key.clone());
+        state.unsaved.push(key);
+    }
+
+    /// Marks `key` seen. Returns `false` when it already was.
+    pub fn mark_seen(&self, key: String) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.seen.insert(key.clone()) {
+            return false;
+        }
+        if state.queued.remove(&key).is_some() {
+            state.changed.insert(key.clone());
+        }
+        state.unsaved.push(key);
+        true
+    }
+
+    /// Entries waiting to be taken.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether job `job_id` left crawl state in `db`, a checkpoint or
+    /// the marker of a finished job.
+    pub fn has_job(db: &rocksdb::DB, job_id: u64) -> bool {
+        let prefix: String = job_prefix(job_id);
+        let mut iter = db.iterator(rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward));
+        iter.next().and_then(Result::ok).is_some_and(|(key, _)| key.starts_with(prefix.as_bytes()))
+    }
+
+    /// Highest job id with crawl state in `db`.
+    pub fn last_job_id(db: &rocksdb::DB) -> Option<u64> {
+        // ';' sorts right after ':', so the key before it is the last `crawl:` key
+        let end: String = CRAWL_PREFIX.replace(':', ";");
+        let mut iter = db.iterator(rocksdb::IteratorMode::From(end.as_bytes(), rocksdb::Direction::Reverse));
+        let (key, _) = iter.next()?.ok()?;
+
+        let id = key.strip_prefix(CRAWL_PREFIX.as_bytes())?.get(..20)?;
+        String::from_utf8_lossy(id).parse::<u64>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testkit::TestCtx;
+
+    use super::*;
+
+    fn entry(url: &str, depth: usize) -> FrontierEntry {
+        FrontierEntry::new(url.to_string(), depth)
+    }
+
+    fn pop_url(frontier: &Frontier) -> Option<String> {
+        frontier.pop(|_| true).map(|entry| entry.url)
+    }
+
+    #[test]
+    fn test_estimate_value() {
+        assert_eq!(estimate_value("https://example.com/"), 1.0);
+        assert!(estimate_value("https://example.com/a/b/c/d.html") < estimate_value("https://example.com/a.html"));
+        assert!(estimate_value("https://example.com/a?page=2") < estimate_value("https://example.com/a"));
+        assert!(estimate_value("https://example.com/manual.pdf") < estimate_value("https://example.com/manual.html"));
+    }
+
+    #[test]
+    fn test_pop_order_follows_depth_and_inlinks() {
+        let ctx = TestCtx::new("frontier-order");
+        let frontier = Frontier::new(ctx.rocks_con.clone(), None, 10);
+        frontier.push(entry("https://a.com/deep", 2));
+        frontier.push(entry("https://a.com/shallow", 1));
+        frontier.push(entry("https://a.com/popular", 1));
+        for _ in 0..3 {
+            frontier.push(entry("https://a.com/popular", 1));
+        }
+
+        assert_eq!(frontier.len(), 3);
+        assert_eq!(pop_url(&frontier).as_deref(), Some("https://a.com/popular"));
+        assert_eq!(pop_url(&frontier).as_deref(), Some("https://a.com/shallow"));
+
+        // found closer to the seed, the entry takes that depth and referrer
+        frontier.push(FrontierEntry { referrer: Some("https://a.com/".to_string()), ..entry("https://a.com/deep", 1) });
+        let deep = frontier.pop(|_| true).unwrap();
+        assert_eq!((deep.depth, deep.inlinks), (1, 1));
+        assert_eq!(deep.referrer.as_deref(), Some("https://a.com/"));
+        assert_eq!(pop_url(&frontier), None);
+
+        // seen pages don't come back
+        frontier.push(entry("https://a.com/shallow", 1));
+        assert!(frontier.is_empty());
+    }
+
+    #[test]
+    fn test_hosts_take_turns_and_respect_slots() {
+        let ctx = TestCtx::new("frontier-hosts");
+        let frontier = Frontier::new(ctx.rocks_con.clone(), None, 1);
+        for path in ["a", "b", "c"] {
+            frontier.push(entry(&format!("https://big.com/{}", path), 1));
+        }
+        frontier.push(entry("https://small.com/a", 1));
+
+        let first = frontier.pop(|_| true).unwrap();
+        let second = frontier.pop(|_| true).unwrap();
+        assert_ne!(host_of(&first.url), host_of(&second.url));
+
+        // both hosts have their only slot taken
+        assert_eq!(pop_url(&frontier), None);
+        let big = if host_of(&first.url) == "big.com" { first } else { second };
+        frontier.complete(&big);
+        assert!(pop_url(&frontier).unwrap().starts_with("https://big.com/"));
+    }
+
+    #[test]
+    fn test_restore_resumes_from_checkpoint() {
+        let ctx = TestCtx::new("frontier-restore");
+        let db = ctx.rocks_con.clone();
+        let frontier = Frontier::new(db.clone(), Some(7), 2);
+        assert!(!frontier.restore().unwrap());
+
+        frontier.push(entry("https://a.com/", 0));
+        let seed = frontier.pop(|_| true).unwrap();
+        frontier.push(entry("https://a.com/next", 1));
+        frontier.push(entry("https://a.com/other", 1));
+        frontier.complete(&seed);
+        let next = frontier.pop(|_| true).unwrap();
+        frontier.checkpoint().unwrap();
+
+        // the page still in flight is queued again, the completed seed isn't
+        let resumed = Frontier::new(db.clone(), Some(7), 2);
+        assert!(resumed.restore().unwrap());
+        resumed.push(entry("https://a.com/", 0));
+        let mut urls: Vec<String> = std::iter::from_fn(|| pop_url(&resumed)).collect();
+        urls.sort();
+        assert_eq!(urls, vec![next.url, "https://a.com/other".to_string()]);
+
+        let unrelated = Frontier::new(db.clone(), Some(8), 2);
+        assert!(!unrelated.restore().unwrap());
+        assert_eq!(Frontier::last_job_id(&db), Some(7));
+
+        // finishing drops the checkpoint but keeps the id taken
+        resumed.finish().unwrap();
+        assert!(!Frontier::new(db.clone(), Some(7), 2).restore().unwrap());
+        assert_eq!(Frontier::last_job_id(&db), Some(7));
+        assert!(Frontier::has_job(&db, 7));
+        assert!(!Frontier::has_job(&db, 8));
+    }
+
+    #[test]
+    fn test_checkpoint_writes_only_changed_entries() {
+        let ctx = TestCtx::new("frontier-incremental");
+        let db = ctx.rocks_con.clone();
+        let frontier = Frontier::new(db.clone(), Some(1), 2);
+        frontier.push(entry("https://a.com/", 0));
+        frontier.push(entry("https://a.com/x", 1));
+        frontier.checkpoint().unwrap();
+
+        let queued_key = |url: &str| format!("{}{}", queued_prefix(1), visit_key(url).unwrap());
+        db.delete(queued_key("https://a.com/x")).unwrap();
+        let seed = frontier.pop(|url| url == "https://a.com/").unwrap();
+        frontier.complete(&seed);
+        frontier.checkpoint().unwrap();
+
+        // the untouched entry isn't written again, the crawled one is gone
+        assert_eq!(db.get(queued_key("https://a.com/x")).unwrap(), None);
+        assert_eq!(db.get(queued_key("https://a.com/")).unwrap(), None);
+        assert!(db.get(format!("{}{}", seen_prefix(1), visit_key("https://a.com/").unwrap())).unwrap().is_some());
+    }
+}
@@ -0,0 +1,132 @@
+//! Which page links to which, as the crawler found them.
+//!
+//! Key layout, both sides normalized URLs:
+//! - `outlinks:{source}`: JSON list of the pages `source` links to
+//! - `inlinks:{target} {source}`: empty, one per link; normalized URLs
+//!   never contain a space, so a target's links share the prefix
+
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use crate::errors::CrawlerError;
+
+const OUTLINKS_PREFIX: &str = "outlinks:";
+
+/// Held while a page's links are replaced, so two crawls recording the
+/// same page can't leave inlinks its final outlinks don't have.
+static SET_OUTLINKS: Mutex<()> = Mutex::new(());
+
+fn outlinks_key(url: &str) -> String {
+    format!("outlinks:{}", url)
+}
+
+fn inlinks_prefix(url: &str) -> String {
+    format!("inlinks:{} ", url)
+}
+
+/// Links between crawled pages.
+#[derive(Debug, Clone)]
+pub struct LinkGraph {
+    db: Arc<rocksdb::DB>
+}
+
+impl LinkGraph {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self { db }
+    }
+
+    /// Replaces the links recorded for `source` with `targets`, in both
+    /// directions.
+    pub fn set_outlinks(&self, source: &str, targets: &[String]) -> Result<(), CrawlerError> {
+        let key: String = outlinks_key(source);
+        let current: HashSet<&String> = targets.iter().collect();
+        let _guard = SET_OUTLINKS.lock().unwrap();
+
+        let mut batch = rocksdb::WriteBatch::default();
+        for target in self.outlinks(source)?.iter().filter(|target| !current.contains(target)) {
+            batch.delete(format!("{}{}", inlinks_prefix(target), source));
+        }
+        for target in targets {
+            batch.put(format!("{}{}", inlinks_prefix(target), source), b"");
+        }
+        let value: Vec<u8> = serde_json::to_vec(targets).map_err(|err| CrawlerError::storage(&key, err))?;
+        batch.put(&key, value);
+
+        self.db.write(batch).map_err(|err| CrawlerError::storage(&key, err))
+    }
+
+    /// Pages `source` links to; none when it wasn't crawled.
+    pub fn outlinks(&self, source: &str) -> Result<Vec<String>, CrawlerError> {
+        let key: String = outlinks_key(source);
+        match self.db.get(&key).map_err(|err| CrawlerError::storage(&key, err))? {
+            Some(value) => serde_json::from_slice(&value).map_err(|err| CrawlerError::storage(&key, err)),
+            None => Ok(vec![])
+        }
+    }
+
+    /// Crawled pages that link to `target`, by URL.
+    pub fn inlinks(&self, target: &str) -> Result<Vec<String>, CrawlerError> {
+        let prefix: String = inlinks_prefix(target);
+        let mut sources: Vec<String> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, _) = key_value.map_err(|err| CrawlerError::storage(&prefix, err))?;
+            let Some(source) = key.strip_prefix(prefix.as_bytes()) else {
+                break;
+            };
+            sources.push(String::from_utf8_lossy(source).into_owned());
+        }
+        Ok(sources)
+    }
+
+    /// Every crawled page with the pages it links to.
+    pub fn edges(&self) -> Result<Vec<(String, Vec<String>)>, CrawlerError> {
+        let mut edges: Vec<(String, Vec<String>)> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(OUTLINKS_PREFIX.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, value) = key_value.map_err(|err| CrawlerError::storage("link graph", err))?;
+            let Some(source) = key.strip_prefix(OUTLINKS_PREFIX.as_bytes()) else {
+                break;
+            };
+            let targets: Vec<String> = serde_json::from_slice(&value).map_err(|err| CrawlerError::storage("link graph", err))?;
+            edges.push((String::from_utf8_lossy(source).into_owned(), targets));
+        }
+        Ok(edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testkit::TestCtx;
+
+    use super::*;
+
+    #[test]
+    fn test_outlinks_and_inlinks_stay_in_sync() {
+        let ctx = TestCtx::new("graph");
+        let graph = LinkGraph::new(ctx.rocks_con.clone());
+
+        let urls = |urls: &[&str]| -> Vec<String> { urls.iter().map(|url| url.to_string()).collect() };
+        graph.set_outlinks("https://a.com/", &urls(&["https://a.com/x", "https://a.com/x/y"])).unwrap();
+        graph.set_outlinks("https://b.com/", &urls(&["https://a.com/x"])).unwrap();
+        assert_eq!(graph.inlinks("https://a.com/x").unwrap(), urls(&["https://a.com/", "https://b.com/"]));
+
+        // the page changed and no longer links to /x
+        graph.set_outlinks("https://a.com/", &urls(&["https://a.com/x/y"])).unwrap();
+        assert_eq!(graph.outlinks("https://a.com/").unwrap(), urls(&["https://a.com/x/y"]));
+        assert_eq!(graph.outlinks("https://c.com/").unwrap(), urls(&[]));
+        assert_eq!(graph.inlinks("https://a.com/x").unwrap(), urls(&["https://b.com/"]));
+        assert_eq!(graph.inlinks("https://a.com/x/y").unwrap(), urls(&["https://a.com/"]));
+        assert_eq!(graph.edges().unwrap(), vec![
+            ("https://a.com/".to_string(), urls(&["https://a.com/x/y"])),
+            ("https://b.com/".to_string(), urls(&["https://a.com/x"]))
+        ]);
+    }
+}
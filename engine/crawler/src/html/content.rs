@@ -0,0 +1,295 @@
+//! Main content of a page: its text split into blocks, with navigation,
+//! link lists and other boilerplate left out.
+//!
+//! Blocks are scored the way boilerpipe does it, by text density (words
+//! per line of wrapped text) and link density (share of the text that is
+//! link text).
+
+/// Elements whose text is never content.
+const SKIPPED_ELEMENTS: [&str; 9] = ["head", "script", "style", "noscript", "template", "nav", "footer", "aside", "iframe"];
+
+/// Elements that don't end the block they're in.
+const INLINE_ELEMENTS: [&str; 24] = [
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "del", "dfn", "em", "font", "i", "ins",
+    "kbd", "mark", "q", "s", "samp", "small", "span", "strong", "sub", "sup"
+];
+
+/// Column text is wrapped at to count lines.
+const LINE_WIDTH: usize = 80;
+
+/// Blocks with more link text than this are link lists, menus and the like.
+const MAX_LINK_DENSITY: f32 = 0.5;
+
+/// Blocks at least this dense are content however their neighbours look.
+const MIN_TEXT_DENSITY: f32 = 10.0;
+
+/// Which blocks of a page are indexed as its text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextExtraction {
+    /// `main_content`: link lists and the sparse blocks around them are
+    /// left out.
+    #[default]
+    MainContent,
+
+    /// Every block of `text_blocks`, as pages were indexed before
+    /// content extraction.
+    FullText
+}
+
+/// The element a block of text comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    /// `<p>`, and text that isn't inside any other block.
+    Paragraph,
+
+    /// `<h1>`-`<h6>`, with the level.
+    Heading(u8),
+
+    /// `<li>`
+    ListItem,
+
+    /// `<pre>`; its whitespace is kept.
+    Preformatted,
+
+    /// `<td>` or `<th>`
+    TableCell,
+
+    /// `<dd>`
+    Definition
+}
+
+impl BlockKind {
+    fn of(name: &str) -> Option<Self> {
+        match name {
+            "p" => Some(BlockKind::Paragraph),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Some(BlockKind::Heading(name.as_bytes()[1] - b'0')),
+            "li" => Some(BlockKind::ListItem),
+            "pre" => Some(BlockKind::Preformatted),
+            "td" | "th" => Some(BlockKind::TableCell),
+            "dd" => Some(BlockKind::Definition),
+            _ => None
+        }
+    }
+}
+
+/// A run of text the page's layout keeps together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextBlock {
+    pub kind: BlockKind,
+
+    /// Whitespace collapsed, except in `Preformatted` blocks.
+    pub text: String,
+
+    /// Words per line of the text wrapped at 80 columns.
+    pub text_density: f32,
+
+    /// Share of the text's characters that are link text, 0 to 1.
+    pub link_density: f32
+}
+
+impl TextBlock {
+    fn is_link_list(&self) -> bool {
+        self.link_density > MAX_LINK_DENSITY
+    }
+}
+
+/// Text of the block being collected.
+struct BlockBuilder {
+    kind: BlockKind,
+    text: String,
+    link_chars: usize
+}
+
+impl BlockBuilder {
+    fn new(kind: BlockKind) -> Self {
+        Self { kind, text: String::new(), link_chars: 0 }
+    }
+
+    /// Ends the block collected so far; text that follows starts a new
+    /// one of the same kind.
+    fn flush(&mut self, blocks: &mut Vec<TextBlock>) {
+        let raw: String = std::mem::take(&mut self.text);
+        let link_chars: usize = std::mem::take(&mut self.link_chars);
+
+        let text: String = if self.kind == BlockKind::Preformatted {
+            raw.trim_matches(['\n', '\r']).trim_end().to_string()
+        } else {
+            raw.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        let chars: usize = text.chars().count();
+        let lines: usize = chars.div_ceil(LINE_WIDTH);
+        blocks.push(TextBlock {
+            kind: self.kind,
+            text_density: text.split_whitespace().count() as f32 / lines as f32,
+            link_density: (link_chars as f32 / chars as f32).min(1.0),
+            text
+        });
+    }
+}
+
+/// Every block of text in `document`, in document order, boilerplate
+/// included. Blocks nested in another one, like a list in a list item,
+/// are blocks of their own.
+pub fn text_blocks(document: &scraper::Html) -> Vec<TextBlock> {
+    let mut blocks: Vec<TextBlock> = vec![];
+    let mut loose = BlockBuilder::new(BlockKind::Paragraph);
+    collect_blocks(document.root_element(), false, &mut loose, &mut blocks);
+    loose.flush(&mut blocks);
+    blocks
+}
+
+fn collect_blocks(elem: scraper::ElementRef, in_link: bool, block: &mut BlockBuilder, blocks: &mut Vec<TextBlock>) {
+    for child in elem.children() {
+        if let scraper::Node::Text(text) = child.value() {
+            block.text.push_str(text);
+            if in_link {
+                block.link_chars += text.split_whitespace().map(|word| word.chars().count() + 1).sum::<usize>();
+            }
+            continue;
+        }
+        let Some(child) = scraper::ElementRef::wrap(child) else {
+            continue;
+        };
+
+        let name: &str = child.value().name();
+        if SKIPPED_ELEMENTS.contains(&name) {
+            continue;
+        }
+        if name == "br" {
+            block.text.push('\n');
+            continue;
+        }
+        if let Some(kind) = BlockKind::of(name) {
+            block.flush(blocks);
+            let mut inner = BlockBuilder::new(kind);
+            collect_blocks(child, in_link, &mut inner, blocks);
+            inner.flush(blocks);
+        } else if INLINE_ELEMENTS.contains(&name) {
+            collect_blocks(child, in_link || name == "a", block, blocks);
+        } else {
+            // `<div>`, `<section>` and the like split the text they're in
+            block.flush(blocks);
+            collect_blocks(child, in_link, block, blocks);
+            block.flush(blocks);
+        }
+    }
+}
+
+/// The blocks of `blocks` that are the page's content. Link lists are
+/// dropped, and so are sparse blocks, such as a short line of text,
+/// unless a neighbour is content rather than a link list. A page where
+/// nothing passes, like one with a single short paragraph, keeps every
+/// block that isn't a link list.
+pub fn main_content(blocks: Vec<TextBlock>) -> Vec<TextBlock> {
+    let keep: Vec<bool> = (0..blocks.len())
+        .map(|i| {
+            let block: &TextBlock = &blocks[i];
+            if block.is_link_list() {
+                return false;
+            }
+            if block.kind == BlockKind::Preformatted || block.text_density >= MIN_TEXT_DENSITY {
+                return true;
+            }
+            let previous: Option<&TextBlock> = i.checked_sub(1).map(|j| &blocks[j]);
+            let next: Option<&TextBlock> = blocks.get(i + 1);
+            [previous, next].into_iter().flatten().any(|neighbour| !neighbour.is_link_list())
+        })
+        .collect();
+
+    if !keep.contains(&true) {
+        return blocks.into_iter().filter(|block| !block.is_link_list()).collect();
+    }
+    blocks.into_iter().zip(keep).filter_map(|(block, keep)| keep.then_some(block)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(html: &str) -> Vec<(BlockKind, String)> {
+        text_blocks(&scraper::Html::parse_document(html)).into_iter().map(|block| (block.kind, block.text)).collect()
+    }
+
+    #[test]
+    fn test_blocks_keep_their_kind_and_boundaries() {
+        let html = r#"<html><head><title>T</title><style>p { color: red }</style></head><body>
+            <h2>Install</h2>
+            <p>Run <code>make</code>,<br>then
+               wait.</p>
+            <ul><li>One <ul><li>Nested</li></ul></li></ul>
+            <pre>
+fn main() {
+    run();
+}
+</pre>
+            <table><tr><th>Name</th><td>Value</td></tr></table>
+            <dl><dt>Term</dt><dd>Meaning</dd></dl>
+            <div>Loose <em>text</em><div>Inner</div></div>
+            <script>track();</script>
+        </body></html>"#;
+
+        assert_eq!(blocks(html), vec![
+            (BlockKind::Heading(2), "Install".to_string()),
+            (BlockKind::Paragraph, "Run make, then wait.".to_string()),
+            (BlockKind::ListItem, "One".to_string()),
+            (BlockKind::ListItem, "Nested".to_string()),
+            (BlockKind::Preformatted, "fn main() {\n    run();\n}".to_string()),
+            (BlockKind::TableCell, "Name".to_string()),
+            (BlockKind::TableCell, "Value".to_string()),
+            (BlockKind::Paragraph, "Term".to_string()),
+            (BlockKind::Definition, "Meaning".to_string()),
+            (BlockKind::Paragraph, "Loose text".to_string()),
+            (BlockKind::Paragraph, "Inner".to_string())
+        ]);
+    }
+
+    #[test]
+    fn test_densities() {
+        let html = r#"<html><body><p>Read <a href="/a">the docs</a></p></body></html>"#;
+        let blocks = text_blocks(&scraper::Html::parse_document(html));
+        assert_eq!(blocks[0].text_density, 3.0);
+        assert!((blocks[0].link_density - 9.0 / 13.0).abs() < 1e-6);
+
+        let long: String = "word ".repeat(32);
+        let blocks = text_blocks(&scraper::Html::parse_document(&format!("<p>{}</p>", long)));
+        assert_eq!(blocks[0].text_density, 16.0);
+        assert_eq!(blocks[0].link_density, 0.0);
+    }
+
+    #[test]
+    fn test_main_content_drops_boilerplate() {
+        let article: String = "The loop runs once for every item of the sequence it is given. ".repeat(3);
+        let html = format!(r#"<html><body>
+            <nav><p>Skipped navigation text</p></nav>
+            <ul><li><a href="/">Home</a></li><li><a href="/blog">Blog</a></li></ul>
+            <div><a href="/login">Log in</a> or <a href="/signup">sign up</a></div>
+            <div>Share</div>
+            <ul><li><a href="/x">X</a></li></ul>
+            <h1>Loops</h1>
+            <p>{}</p>
+            <p>Short note.</p>
+            <aside><p>Related posts</p></aside>
+            <footer><p>Copyright</p></footer>
+        </body></html>"#, article);
+
+        let content = main_content(text_blocks(&scraper::Html::parse_document(&html)));
+        let texts: Vec<&str> = content.iter().map(|block| block.text.as_str()).collect();
+        assert_eq!(texts, vec!["Loops", article.trim(), "Short note."]);
+    }
+
+    #[test]
+    fn test_main_content_falls_back_to_non_link_blocks() {
+        let content = main_content(text_blocks(&scraper::Html::parse_document("<p>Hello world</p>")));
+        let texts: Vec<&str> = content.iter().map(|block| block.text.as_str()).collect();
+        assert_eq!(texts, vec!["Hello world"]);
+
+        let html = r#"<ul><li><a href="/">Home</a></li></ul><p>Hello world</p>"#;
+        let content = main_content(text_blocks(&scraper::Html::parse_document(html)));
+        let texts: Vec<&str> = content.iter().map(|block| block.text.as_str()).collect();
+        assert_eq!(texts, vec!["Hello world"]);
+    }
+}
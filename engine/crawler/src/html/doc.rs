@@ -1,15 +1,130 @@
 use lazy_static::lazy_static;
 
+use crate::{
+    robots::product_token,
+    html::content::{main_content, text_blocks, BlockKind, TextBlock, TextExtraction}
+};
+
 lazy_static! {
     static ref A_SELECTOR: scraper::Selector = scraper::Selector::parse("a").unwrap();
-    static ref P_SELECTOR: scraper::Selector = scraper::Selector::parse("p").unwrap();
     static ref TITLE_SELECTOR: scraper::Selector = scraper::Selector::parse("title").unwrap();
+    static ref HEADING_SELECTOR: scraper::Selector = scraper::Selector::parse("h1, h2, h3, h4, h5, h6").unwrap();
+    static ref META_DESCRIPTION_SELECTOR: scraper::Selector = scraper::Selector::parse(r#"meta[name="description" i]"#).unwrap();
+    static ref META_ROBOTS_SELECTOR: scraper::Selector = scraper::Selector::parse(r#"meta[name="robots" i][content]"#).unwrap();
+    static ref CANONICAL_SELECTOR: scraper::Selector = scraper::Selector::parse(r#"link[rel~="canonical" i][href]"#).unwrap();
+    static ref BASE_SELECTOR: scraper::Selector = scraper::Selector::parse("base[href]").unwrap();
+}
+
+/// A link on the page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// Absolute URL the link points to.
+    pub url: String,
+
+    /// Visible text of the anchor, whitespace collapsed; may be empty.
+    pub anchor_text: String,
+
+    /// Values of the `rel` attribute, lowercased, e.g. `nofollow`.
+    pub rel: Vec<String>
+}
+
+impl Link {
+    /// The page asks crawlers not to follow this link.
+    pub fn is_nofollow(&self) -> bool {
+        self.rel.iter().any(|rel| rel == "nofollow")
+    }
+}
+
+/// Directives that take a `name: value` form, so `X-Robots-Tag` values
+/// starting with them aren't meant for a single crawler.
+const VALUED_DIRECTIVES: [&str; 4] = ["unavailable_after", "max-snippet", "max-image-preview", "max-video-preview"];
+
+/// What a page allows crawlers to do with it, from `<meta name="robots">`
+/// and the `X-Robots-Tag` response header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RobotsDirectives {
+    /// The page must not be indexed.
+    pub noindex: bool,
+
+    /// The links on the page must not be followed.
+    pub nofollow: bool
+}
+
+impl RobotsDirectives {
+    /// Directives of a comma separated list such as `noindex, nofollow`;
+    /// `none` stands for both. Directives that don't restrict indexing or
+    /// following are ignored.
+    pub fn parse(value: &str) -> Self {
+        let mut directives = RobotsDirectives::default();
+        for directive in value.split(',').map(|directive| directive.trim().to_ascii_lowercase()) {
+            match directive.as_str() {
+                "noindex" => directives.noindex = true,
+                "nofollow" => directives.nofollow = true,
+                "none" => directives = RobotsDirectives { noindex: true, nofollow: true },
+                _ => {}
+            }
+        }
+        directives
+    }
+
+    /// Directives of an `X-Robots-Tag` header value for `user_agent`. A
+    /// value may name the crawler it's for (`otherbot: noindex`), values
+    /// for other crawlers don't apply.
+    pub fn from_header(value: &str, user_agent: &str) -> Self {
+        if let Some((name, directives)) = value.split_once(':') {
+            let name: String = name.trim().to_ascii_lowercase();
+            if !name.contains(',') && !VALUED_DIRECTIVES.contains(&name.as_str()) {
+                return if name == product_token(user_agent) {
+                    Self::parse(directives)
+                } else {
+                    RobotsDirectives::default()
+                };
+            }
+        }
+        Self::parse(value)
+    }
+
+    /// Every directive of `self` and `other`.
+    pub fn union(self, other: RobotsDirectives) -> Self {
+        RobotsDirectives {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow
+        }
+    }
+}
+
+/// An `href` that couldn't be turned into an absolute URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedLink {
+    pub href: String,
+    pub reason: String
 }
 
 #[derive(Debug)]
 pub struct HtmlDoc {
     pub title: String,
     pub url: String,
+
+    /// Text of every `<h1>`-`<h6>`, in document order.
+    pub headings: Vec<String>,
+
+    /// Content of `<meta name="description">`.
+    pub description: Option<String>,
+
+    /// `href` of `<link rel="canonical">` as written, possibly relative.
+    pub canonical_url: Option<String>,
+
+    /// Directives of `<meta name="robots">`; the crawler adds those of
+    /// the `X-Robots-Tag` header.
+    pub robots: RobotsDirectives,
+
+    /// What relative links are resolved against: `<base href>` if the
+    /// page has one, else `url`.
+    base_url: Option<url::Url>,
+
+    /// Which blocks `content_blocks` and `text` keep; set it before the
+    /// first call to `text`.
+    pub text_extraction: TextExtraction,
     text_content: Option<String>,
     html: Option<scraper::Html>
 }
@@ -22,39 +137,122 @@ impl HtmlDoc {
                                 .map(|elem| elem.inner_html())
                                 .unwrap_or_else(|| "No Title".to_string());
 
+        let headings: Vec<String> = document.select(&HEADING_SELECTOR)
+                                .map(|elem| Self::normalize_whitespace(elem.text()))
+                                .filter(|heading| !heading.is_empty())
+                                .collect();
+
+        let description: Option<String> = document.select(&META_DESCRIPTION_SELECTOR).next()
+                                .and_then(|elem| elem.value().attr("content"))
+                                .map(|content| Self::normalize_whitespace(std::iter::once(content)))
+                                .filter(|content| !content.is_empty());
+
+        let canonical_url: Option<String> = document.select(&CANONICAL_SELECTOR).next()
+                                .and_then(|elem| elem.value().attr("href"))
+                                .map(|href| href.trim().to_string())
+                                .filter(|href| !href.is_empty());
+
+        let robots: RobotsDirectives = document.select(&META_ROBOTS_SELECTOR)
+                                .filter_map(|elem| elem.value().attr("content"))
+                                .map(RobotsDirectives::parse)
+                                .fold(RobotsDirectives::default(), RobotsDirectives::union);
+
+        let page_url: Option<url::Url> = url::Url::parse(&url).ok();
+        let base_url: Option<url::Url> = document.select(&BASE_SELECTOR).next()
+                                .and_then(|elem| elem.value().attr("href"))
+                                .and_then(|href| match &page_url {
+                                    Some(page_url) => page_url.join(href.trim()).ok(),
+                                    None => url::Url::parse(href.trim()).ok()
+                                })
+                                .or(page_url);
+
         HtmlDoc {
             text_content: None,
             title,
             url,
+            headings,
+            description,
+            canonical_url,
+            robots,
+            base_url,
+            text_extraction: TextExtraction::default(),
             html: Some(document)
         }
     }
 
-    pub fn text(&mut self) -> Option<&str> {
-        if self.text_content.is_none() {
-            let document = self.html.as_ref()?;
-            let mut body = String::new();
+    fn normalize_whitespace<'a>(text: impl Iterator<Item = &'a str>) -> String {
+        text.flat_map(str::split_whitespace).collect::<Vec<_>>().join(" ")
+    }
 
-            for p in document.select(&P_SELECTOR) {
-                let txt = p.text().collect::<Vec<_>>().join(" ");
-                body.push_str(&txt);
-            }
+    /// The page's content, one block per paragraph, heading, list item
+    /// and so on, in document order. Navigation, footers, asides and, with
+    /// `TextExtraction::MainContent`, link lists are left out.
+    pub fn content_blocks(&self) -> Vec<TextBlock> {
+        let Some(document) = self.html.as_ref() else {
+            return vec![];
+        };
+        match self.text_extraction {
+            TextExtraction::MainContent => main_content(text_blocks(document)),
+            TextExtraction::FullText => text_blocks(document)
+        }
+    }
 
-            self.text_content = Some(body);
+    /// Body text: `content_blocks` but the headings, which are indexed as
+    /// `headings`, blocks separated by a blank line.
+    pub fn text(&mut self) -> Option<&str> {
+        if self.text_content.is_none() {
+            self.html.as_ref()?;
+            let blocks: Vec<String> = self.content_blocks().into_iter()
+                .filter(|block| !matches!(block.kind, BlockKind::Heading(_)))
+                .map(|block| block.text)
+                .collect();
+            self.text_content = Some(blocks.join("\n\n"));
         }
     
         self.text_content.as_deref()
     }
 
-    pub fn extract_links(&self) -> Vec<String> {
-        if self.html.is_none() {
-            return vec![];
-        }
+    /// The page's links with absolute URLs, resolved against `<base href>`
+    /// or the page's own URL. Links within the page (`#section`) and hrefs
+    /// that don't resolve are left out, see `resolve_links`.
+    pub fn extract_links(&self) -> Vec<Link> {
+        self.resolve_links().0
+    }
+
+    /// `extract_links`, along with the hrefs it leaves out because they
+    /// don't form a URL.
+    pub fn resolve_links(&self) -> (Vec<Link>, Vec<SkippedLink>) {
+        let Some(document) = self.html.as_ref() else {
+            return (vec![], vec![]);
+        };
+
+        let mut links: Vec<Link> = vec![];
+        let mut skipped: Vec<SkippedLink> = vec![];
+        for elem in document.select(&A_SELECTOR) {
+            let Some(href) = elem.value().attr("href") else {
+                continue;
+            };
+            let href: &str = href.trim();
+            if href.is_empty() || href.starts_with('#') {
+                continue;
+            }
 
-        self.html.as_ref().unwrap().select(&A_SELECTOR)
-            .filter_map(|elem| elem.value().attr("href"))
-            .map(|href| href.to_string())
-            .collect()
+            let resolved = match &self.base_url {
+                Some(base) => base.join(href),
+                None => url::Url::parse(href)
+            };
+            match resolved {
+                Ok(url) => links.push(Link {
+                    url: url.into(),
+                    anchor_text: Self::normalize_whitespace(elem.text()),
+                    rel: elem.value().attr("rel")
+                        .map(|rel| rel.split_whitespace().map(str::to_ascii_lowercase).collect())
+                        .unwrap_or_default()
+                }),
+                Err(err) => skipped.push(SkippedLink { href: href.to_string(), reason: err.to_string() })
+            }
+        }
+        (links, skipped)
     }
 
     pub fn preprocess<F>(&mut self, f: F) 
@@ -74,9 +272,15 @@ mod tests {
     fn sample_html() -> String {
         r#"
         <html>
-            <head><title>Test Page</title></head>
+            <head>
+                <title>Test Page</title>
+                <meta name="description" content="A page   used in tests.">
+                <link rel="canonical" href="/test-page">
+            </head>
             <body>
+                <h1>Main <em>heading</em></h1>
                 <p>Hello, this is a test.</p>
+                <h3>Details</h3>
                 <p>More content here.</p>
                 <a href="https://example.com">Example</a>
                 <a href="/local">Local Link</a>
@@ -91,6 +295,22 @@ mod tests {
         assert_eq!(doc.title, "Test Page");
     }
 
+    #[test]
+    fn test_parse_headings_and_description() {
+        let doc = HtmlDoc::parse("http://test.com".to_string(), sample_html());
+        assert_eq!(doc.headings, vec!["Main heading", "Details"]);
+        assert_eq!(doc.description.as_deref(), Some("A page used in tests."));
+    }
+
+    #[test]
+    fn test_parse_canonical_url() {
+        let doc = HtmlDoc::parse("http://test.com".to_string(), sample_html());
+        assert_eq!(doc.canonical_url.as_deref(), Some("/test-page"));
+
+        let doc = HtmlDoc::parse("url".to_string(), "<html><head></head></html>".to_string());
+        assert_eq!(doc.canonical_url, None);
+    }
+
     #[test]
     fn test_extract_text() {
         let mut doc = HtmlDoc::parse("http://test.com".to_string(), sample_html());
@@ -99,13 +319,106 @@ mod tests {
         assert!(text.contains("More content here."));
     }
 
+    #[test]
+    fn test_text_keeps_block_boundaries_and_drops_boilerplate() {
+        let html = r#"<html><body>
+            <nav><a href="/">Home</a> <a href="/docs">Docs</a></nav>
+            <h1>Guide</h1>
+            <p>First paragraph.</p><p>Second paragraph.</p>
+            <ul><li>An item</li></ul>
+            <footer><p>Copyright</p></footer>
+        </body></html>"#;
+        let mut doc = HtmlDoc::parse("url".to_string(), html.to_string());
+        assert_eq!(doc.text(), Some("First paragraph.\n\nSecond paragraph.\n\nAn item"));
+
+        let kinds: Vec<crate::BlockKind> = doc.content_blocks().into_iter().map(|block| block.kind).collect();
+        assert_eq!(kinds, vec![crate::BlockKind::Heading(1), crate::BlockKind::Paragraph, crate::BlockKind::Paragraph, crate::BlockKind::ListItem]);
+    }
+
+    #[test]
+    fn test_full_text_keeps_link_lists() {
+        let html = r#"<html><body>
+            <ul><li><a href="/">Home</a></li><li><a href="/docs">Docs</a></li></ul>
+            <p>First paragraph.</p>
+        </body></html>"#;
+        let mut doc = HtmlDoc::parse("url".to_string(), html.to_string());
+        doc.text_extraction = TextExtraction::FullText;
+        assert_eq!(doc.text(), Some("Home\n\nDocs\n\nFirst paragraph."));
+    }
+
     #[test]
     fn test_extract_links() {
         let doc = HtmlDoc::parse("http://test.com".to_string(), sample_html());
         let links = doc.extract_links();
         assert_eq!(links.len(), 2);
-        assert!(links.contains(&"https://example.com".to_string()));
-        assert!(links.contains(&"/local".to_string()));
+        assert_eq!(links[0].url, "https://example.com/");
+        assert_eq!(links[1].url, "http://test.com/local");
+        assert_eq!(links[1].anchor_text, "Local Link");
+    }
+
+    #[test]
+    fn test_links_carry_anchor_text_and_rel() {
+        let html = r#"<html><body><a href="/a" rel="NoFollow  sponsored">Read <b>the</b>
+            guide</a><a href="/b"><img src="b.png"></a></body></html>"#;
+        let doc = HtmlDoc::parse("http://test.com/".to_string(), html.to_string());
+        assert_eq!(doc.extract_links(), vec![
+            Link {
+                url: "http://test.com/a".to_string(),
+                anchor_text: "Read the guide".to_string(),
+                rel: vec!["nofollow".to_string(), "sponsored".to_string()]
+            },
+            Link { url: "http://test.com/b".to_string(), anchor_text: String::new(), rel: vec![] }
+        ]);
+    }
+
+    #[test]
+    fn test_robots_directives() {
+        let doc = HtmlDoc::parse("url".to_string(), sample_html());
+        assert_eq!(doc.robots, RobotsDirectives::default());
+
+        let html = r#"<html><head><meta name="ROBOTS" content="NoIndex, noarchive"><meta name="robots" content="nofollow"></head></html>"#;
+        let doc = HtmlDoc::parse("url".to_string(), html.to_string());
+        assert_eq!(doc.robots, RobotsDirectives { noindex: true, nofollow: true });
+
+        assert_eq!(RobotsDirectives::parse("none"), RobotsDirectives { noindex: true, nofollow: true });
+        assert_eq!(RobotsDirectives::parse("index, follow"), RobotsDirectives::default());
+
+        let agent = "SpyBot/0.1 (search engine crawler)";
+        assert!(RobotsDirectives::from_header("noindex", agent).noindex);
+        assert!(RobotsDirectives::from_header("spybot: noindex", agent).noindex);
+        assert!(!RobotsDirectives::from_header("otherbot: noindex", agent).noindex);
+        assert!(RobotsDirectives::from_header("nofollow, unavailable_after: 25 Jun 2010 15:00:00 PST", agent).nofollow);
+        assert!(RobotsDirectives::from_header("max-snippet: 20, noindex", agent).noindex);
+    }
+
+    #[test]
+    fn test_links_resolve_against_page_and_base() {
+        let html = r##"<html><body><a href="next.html">Next</a><a href="../up">Up</a><a href="#top">Top</a></body></html>"##;
+        let doc = HtmlDoc::parse("https://example.com/docs/guide/intro.html".to_string(), html.to_string());
+        let urls: Vec<String> = doc.extract_links().into_iter().map(|link| link.url).collect();
+        assert_eq!(urls, vec!["https://example.com/docs/guide/next.html", "https://example.com/docs/up"]);
+
+        let html = r#"<html><head><base href="/v2/"></head><body><a href="next.html">Next</a></body></html>"#;
+        let doc = HtmlDoc::parse("https://example.com/docs/guide/intro.html".to_string(), html.to_string());
+        let urls: Vec<String> = doc.extract_links().into_iter().map(|link| link.url).collect();
+        assert_eq!(urls, vec!["https://example.com/v2/next.html"]);
+    }
+
+    #[test]
+    fn test_malformed_links_are_skipped() {
+        let html = r#"<html><body><a href="http://[broken">Broken</a><a href="/ok">Ok</a></body></html>"#;
+        let doc = HtmlDoc::parse("http://test.com/".to_string(), html.to_string());
+        let (links, skipped) = doc.resolve_links();
+        let urls: Vec<String> = links.into_iter().map(|link| link.url).collect();
+        assert_eq!(urls, vec!["http://test.com/ok"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].href, "http://[broken");
+
+        // without an absolute page URL, relative links can't be resolved
+        let doc = HtmlDoc::parse("url".to_string(), html.to_string());
+        let (links, skipped) = doc.resolve_links();
+        assert!(links.is_empty());
+        assert_eq!(skipped.len(), 2);
     }
 
     #[test]
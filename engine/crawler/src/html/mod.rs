@@ -1,3 +1,5 @@
+mod content;
 mod doc;
 
+pub use content::*;
 pub use doc::*;
\ No newline at end of file
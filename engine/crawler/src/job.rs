@@ -0,0 +1,333 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        Mutex
+    }
+};
+
+use indexer::Indexer;
+use tokio::sync::watch;
+
+use crate::{
+    errors::{CrawlerError, JobError}, 
+    CrawlFailure, 
+    Crawler, 
+    CrawlerOptions, 
+    FailureLog, 
+    FetchOptions, 
+    Frontier
+};
+
+/// Lifecycle of a crawl job.
+///
+/// ```text
+/// Queued -> Running <-> Paused
+///    \         |          /
+///     `-> Cancelled <----'     Running -> Done
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Cancelled,
+    Done
+}
+
+impl JobState {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Cancelled => "cancelled",
+            JobState::Done => "done"
+        }
+    }
+
+    /// Cancelled and done jobs never change state again.
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Cancelled | JobState::Done)
+    }
+
+    fn can_become(self, next: JobState) -> bool {
+        matches!(
+            (self, next),
+            (JobState::Queued, JobState::Running)
+                | (JobState::Running, JobState::Paused)
+                | (JobState::Paused, JobState::Running)
+                | (JobState::Running, JobState::Done)
+                | (JobState::Queued | JobState::Running | JobState::Paused, JobState::Cancelled)
+        )
+    }
+}
+
+/// Snapshot of a job's state and counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub id: u64,
+    pub seed_url: String,
+    pub state: JobState,
+
+    /// Pages fetched with a body; pages that answered "not modified"
+    /// only count as unchanged.
+    pub pages_fetched: usize,
+    pub pages_failed: usize,
+
+    /// Pages not indexed again because they didn't change since the
+    /// last crawl.
+    pub pages_unchanged: usize,
+
+    /// Links waiting in the frontier.
+    pub pages_queued: usize,
+    pub current_depth: usize,
+
+    /// Links dropped because their href doesn't form a URL.
+    pub links_skipped: usize
+}
+
+/// Shared between a running `Crawler` and whoever controls it. The
+/// crawler checks in with `wait_until_runnable` before every fetch, which
+/// is where pausing and cancelling take effect.
+#[derive(Debug)]
+pub struct JobControl {
+    state: watch::Sender<JobState>,
+    pub(crate) pages_fetched: AtomicUsize,
+    pub(crate) pages_failed: AtomicUsize,
+    pub(crate) pages_unchanged: AtomicUsize,
+    pub(crate) pages_queued: AtomicUsize,
+    pub(crate) current_depth: AtomicUsize,
+    pub(crate) links_skipped: AtomicUsize
+}
+
+impl Default for JobControl {
+    fn default() -> Self {
+        Self {
+            state: watch::Sender::new(JobState::Queued),
+            pages_fetched: AtomicUsize::new(0),
+            pages_failed: AtomicUsize::new(0),
+            pages_unchanged: AtomicUsize::new(0),
+            pages_queued: AtomicUsize::new(0),
+            current_depth: AtomicUsize::new(0),
+            links_skipped: AtomicUsize::new(0)
+        }
+    }
+}
+
+impl JobControl {
+    pub fn state(&self) -> JobState {
+        *self.state.borrow()
+    }
+
+    /// Moves to `next` if the state machine allows it and returns the
+    /// state the job was in before.
+    pub fn transition(&self, next: JobState) -> Result<JobState, JobState> {
+        let mut previous: Result<JobState, JobState> = Err(next);
+        self.state.send_if_modified(|state| {
+            if state.can_become(next) {
+                previous = Ok(*state);
+                *state = next;
+                true
+            } else {
+                previous = Err(*state);
+                false
+            }
+        });
+        previous
+    }
+
+    /// Waits while the job is paused. Returns `false` once the job was
+    /// cancelled, in which case the crawler should stop.
+    pub async fn wait_until_runnable(&self) -> bool {
+        let mut state = self.state.subscribe();
+        let Ok(state) = state.wait_for(|state| *state != JobState::Paused).await else {
+            return false;
+        };
+        *state != JobState::Cancelled
+    }
+
+    pub(crate) fn record_fetch(&self, ok: bool) {
+        let counter = if ok { &self.pages_fetched } else { &self.pages_failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Job {
+    seed_url: String,
+    control: Arc<JobControl>
+}
+
+/// Crawl jobs started in this process, by id.
+#[derive(Clone)]
+pub struct JobRegistry {
+    indexer: Arc<Indexer>,
+    jobs: Arc<Mutex<BTreeMap<u64, Job>>>,
+    next_id: Arc<AtomicU64>,
+
+    /// HTTP clients by user agent and fetch options, so jobs that agree
+    /// on them share connections.
+    clients: Arc<Mutex<Vec<(String, FetchOptions, reqwest::Client)>>>,
+
+    /// Jobs are spawned here, so the registry can be driven from threads
+    /// outside the runtime (e.g. the blocking HTTP server).
+    runtime: tokio::runtime::Handle
+}
+
+impl JobRegistry {
+    /// Must be called from within a Tokio runtime. Ids continue after
+    /// the jobs checkpointed by earlier runs.
+    pub fn new(indexer: Arc<Indexer>) -> Self {
+        let next_id: u64 = Frontier::last_job_id(&indexer.ctx().rocks_con).map_or(1, |id| id + 1);
+
+        Self {
+            indexer,
+            jobs: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(next_id)),
+            clients: Arc::new(Mutex::new(vec![])),
+            runtime: tokio::runtime::Handle::current()
+        }
+    }
+
+    /// Queues a crawl of `options.seed_url` and returns the job id. With
+    /// `options.job_id` set, a job checkpointed under that id resumes.
+    pub fn start(&self, mut options: CrawlerOptions) -> Result<u64, JobError> {
+        let control: Arc<JobControl> = Arc::new(JobControl::default());
+        let id: u64 = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let id: u64 = match options.job_id {
+                Some(id) => {
+                    if jobs.get(&id).is_some_and(|job| !job.control.state().is_finished()) {
+                        return Err(JobError::AlreadyRunning(id));
+                    }
+                    self.next_id.fetch_max(id + 1, Ordering::Relaxed);
+                    id
+                }
+                None => self.next_id.fetch_add(1, Ordering::Relaxed)
+            };
+
+            jobs.insert(id, Job {
+                seed_url: options.seed_url.clone(),
+                control: control.clone()
+            });
+            id
+        };
+        options.job_id = Some(id);
+        if options.client.is_none() {
+            options.client = Some(self.client(&options.user_agent, &options.fetch));
+        }
+
+        let crawler = Crawler::with_control(self.indexer.clone(), options, control);
+        self.runtime.spawn(async move {
+            if let Err(err) = crawler.start_crawling().await {
+                eprintln!("Crawl job {} failed: {}", id, err);
+            }
+        });
+        Ok(id)
+    }
+
+    pub fn pause(&self, id: u64) -> Result<JobStatus, JobError> {
+        self.transition(id, JobState::Paused)
+    }
+
+    pub fn resume(&self, id: u64) -> Result<JobStatus, JobError> {
+        self.transition(id, JobState::Running)
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<JobStatus, JobError> {
+        self.transition(id, JobState::Cancelled)
+    }
+
+    pub fn status(&self, id: u64) -> Result<JobStatus, JobError> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(&id).map(|job| Self::snapshot(id, job)).ok_or(JobError::NotFound(id))
+    }
+
+    /// Every job, oldest first.
+    pub fn list(&self) -> Vec<JobStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().map(|(id, job)| Self::snapshot(*id, job)).collect()
+    }
+
+    /// URLs job `id` failed to fetch and hasn't fetched since, including
+    /// jobs of earlier runs. `None` when there never was such a job.
+    pub fn failures(&self, id: u64) -> Result<Option<Vec<CrawlFailure>>, CrawlerError> {
+        let db: &Arc<rocksdb::DB> = &self.indexer.ctx().rocks_con;
+        if !self.jobs.lock().unwrap().contains_key(&id) && !Frontier::has_job(db, id) {
+            return Ok(None);
+        }
+        FailureLog::new(db.clone()).list(Some(id)).map(Some)
+    }
+
+    fn client(&self, user_agent: &str, fetch: &FetchOptions) -> reqwest::Client {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some((_, _, client)) = clients.iter().find(|(agent, options, _)| agent == user_agent && options == fetch) {
+            return client.clone();
+        }
+
+        let client: reqwest::Client = fetch.client(user_agent);
+        clients.push((user_agent.to_string(), *fetch, client.clone()));
+        client
+    }
+
+    fn transition(&self, id: u64, next: JobState) -> Result<JobStatus, JobError> {
+        let jobs = self.jobs.lock().unwrap();
+        let job: &Job = jobs.get(&id).ok_or(JobError::NotFound(id))?;
+
+        job.control.transition(next)
+            .map_err(|from| JobError::InvalidTransition { id, from, to: next })?;
+        Ok(Self::snapshot(id, job))
+    }
+
+    fn snapshot(id: u64, job: &Job) -> JobStatus {
+        let control: &JobControl = &job.control;
+        JobStatus {
+            id,
+            seed_url: job.seed_url.clone(),
+            state: control.state(),
+            pages_fetched: control.pages_fetched.load(Ordering::Relaxed),
+            pages_failed: control.pages_failed.load(Ordering::Relaxed),
+            pages_unchanged: control.pages_unchanged.load(Ordering::Relaxed),
+            pages_queued: control.pages_queued.load(Ordering::Relaxed),
+            current_depth: control.current_depth.load(Ordering::Relaxed),
+            links_skipped: control.links_skipped.load(Ordering::Relaxed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_machine() {
+        let control = JobControl::default();
+        assert_eq!(control.transition(JobState::Paused), Err(JobState::Queued));
+        assert_eq!(control.transition(JobState::Running), Ok(JobState::Queued));
+        assert_eq!(control.transition(JobState::Paused), Ok(JobState::Running));
+        assert_eq!(control.transition(JobState::Done), Err(JobState::Paused));
+        assert_eq!(control.transition(JobState::Running), Ok(JobState::Paused));
+        assert_eq!(control.transition(JobState::Cancelled), Ok(JobState::Running));
+        assert_eq!(control.transition(JobState::Running), Err(JobState::Cancelled));
+        assert!(control.state().is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_pause_blocks_until_resumed_or_cancelled() {
+        let control = Arc::new(JobControl::default());
+        control.transition(JobState::Running).unwrap();
+        assert!(control.wait_until_runnable().await);
+
+        control.transition(JobState::Paused).unwrap();
+        let waiter = tokio::spawn({
+            let control = control.clone();
+            async move { control.wait_until_runnable().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        control.transition(JobState::Cancelled).unwrap();
+        assert!(!waiter.await.unwrap());
+    }
+}
@@ -1,8 +1,31 @@
-pub mod tfidf;
 pub mod errors;
 
 mod crawler;
 mod html;
+mod job;
+mod robots;
+mod politeness;
+mod scope;
+mod normalize;
+mod frontier;
+mod sitemap;
+mod recrawl;
+mod failures;
+mod fetch;
+mod graph;
+mod pagerank;
 
 pub use crawler::*;
-pub use html::*;
\ No newline at end of file
+pub use html::*;
+pub use job::*;
+pub use robots::*;
+pub use politeness::*;
+pub use scope::*;
+pub use normalize::*;
+pub use frontier::*;
+pub use sitemap::*;
+pub use recrawl::*;
+pub use failures::*;
+pub use fetch::*;
+pub use graph::*;
+pub use pagerank::*;
\ No newline at end of file
@@ -0,0 +1,76 @@
+//! URL canonicalization, so one page is crawled once no matter how a
+//! link spells its address.
+
+/// Query parameters that only track where a visitor came from.
+const TRACKING_PARAMS: [&str; 8] = ["gclid", "fbclid", "msclkid", "dclid", "mc_cid", "mc_eid", "_ga", "yclid"];
+
+fn is_tracking_param(name: &str) -> bool {
+    let name: String = name.to_ascii_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str())
+}
+
+/// Canonical form of `url`: lowercase scheme and host, no default port,
+/// dot segments resolved, no fragment, tracking parameters dropped and
+/// the remaining ones sorted. `None` when `url` isn't absolute.
+pub fn normalize_url(url: &str) -> Option<String> {
+    // parsing already lowercases the host, drops default ports and resolves dot segments
+    let mut url: url::Url = url::Url::parse(url.trim()).ok()?;
+    url.set_fragment(None);
+
+    let mut params: Vec<(String, String)> = url.query_pairs()
+        .filter(|(name, _)| !is_tracking_param(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    params.sort();
+
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+
+    if url.path().is_empty() {
+        url.set_path("/");
+    }
+    Some(url.into())
+}
+
+/// Key for the set of visited pages: the canonical URL without its
+/// scheme, so the http and https variants of a page count as one.
+pub fn visit_key(url: &str) -> Option<String> {
+    let normalized: String = normalize_url(url)?;
+    match normalized.split_once("://") {
+        Some((_, rest)) => Some(rest.to_string()),
+        None => Some(normalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("HTTPS://Docs.Python.org:443/3/./tutorial/../index.html#intro").as_deref(),
+            Some("https://docs.python.org/3/index.html"));
+        assert_eq!(normalize_url("http://example.com:80").as_deref(), Some("http://example.com/"));
+        assert_eq!(normalize_url("http://example.com:8080/a").as_deref(), Some("http://example.com:8080/a"));
+        assert_eq!(normalize_url("/relative"), None);
+    }
+
+    #[test]
+    fn test_query_params_are_cleaned_and_sorted() {
+        assert_eq!(normalize_url("https://example.com/page.html?utm_source=x&b=2&a=1&fbclid=abc").as_deref(),
+            Some("https://example.com/page.html?a=1&b=2"));
+        assert_eq!(normalize_url("https://example.com/page.html?utm_medium=mail").as_deref(),
+            Some("https://example.com/page.html"));
+    }
+
+    #[test]
+    fn test_visit_key_ignores_scheme_and_fragment() {
+        let key = visit_key("https://example.com/page.html");
+        assert_eq!(visit_key("http://example.com/page.html#section"), key);
+        assert_eq!(visit_key("https://EXAMPLE.com/page.html?utm_source=x"), key);
+        assert_ne!(visit_key("https://example.com/PAGE.html"), key);
+    }
+}
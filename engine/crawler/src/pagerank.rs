@@ -0,0 +1,177 @@
+//! PageRank over the link graph, stored with each indexed document so
+//! search can mix it into the ranking.
+
+use std::{
+    collections::HashMap,
+    sync::Arc
+};
+
+use indexer::{IndexReader, Indexer};
+
+use crate::{errors::CrawlerError, LinkGraph};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageRankOptions {
+    /// Chance that a surfer follows a link rather than jumping to a
+    /// random page.
+    pub damping: f64,
+
+    pub max_iterations: usize,
+
+    /// Iteration stops once the ranks move less than this in total.
+    pub tolerance: f64
+}
+
+impl Default for PageRankOptions {
+    fn default() -> Self {
+        Self {
+            damping: 0.85,
+            max_iterations: 50,
+            tolerance: 1e-6
+        }
+    }
+}
+
+/// PageRank of every page in `edges` (page, pages it links to), linked
+/// only pages included. Ranks are scaled so they average 1.0. Links of a
+/// page to itself don't count, and pages without links share their rank
+/// with every page.
+pub fn pagerank(edges: &[(String, Vec<String>)], options: &PageRankOptions) -> HashMap<String, f64> {
+    let mut ids: HashMap<&str, usize> = HashMap::new();
+    for (source, targets) in edges {
+        for url in std::iter::once(source).chain(targets) {
+            let next_id: usize = ids.len();
+            ids.entry(url.as_str()).or_insert(next_id);
+        }
+    }
+    let n: usize = ids.len();
+    if n == 0 {
+        return HashMap::new();
+    }
+
+    let mut links: Vec<Vec<usize>> = vec![vec![]; n];
+    for (source, targets) in edges {
+        let source: usize = ids[source.as_str()];
+        let mut targets: Vec<usize> = targets.iter().map(|target| ids[target.as_str()]).filter(|target| *target != source).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        links[source] = targets;
+    }
+
+    let mut ranks: Vec<f64> = vec![1.0 / n as f64; n];
+    for _ in 0..options.max_iterations {
+        let dangling: f64 = (0..n).filter(|page| links[*page].is_empty()).map(|page| ranks[page]).sum();
+        let base: f64 = (1.0 - options.damping + options.damping * dangling) / n as f64;
+
+        let mut next: Vec<f64> = vec![base; n];
+        for (page, targets) in links.iter().enumerate() {
+            let share: f64 = options.damping * ranks[page] / targets.len().max(1) as f64;
+            for target in targets {
+                next[*target] += share;
+            }
+        }
+
+        let delta: f64 = ranks.iter().zip(&next).map(|(old, new)| (old - new).abs()).sum();
+        ranks = next;
+        if delta < options.tolerance {
+            break;
+        }
+    }
+
+    ids.into_iter().map(|(url, id)| (url.to_string(), ranks[id] * n as f64)).collect()
+}
+
+/// Batch job that computes PageRank over the stored link graph and
+/// writes it to the index.
+#[derive(Clone)]
+pub struct PageRankJob {
+    indexer: Arc<Indexer>,
+    options: PageRankOptions
+}
+
+impl PageRankJob {
+    pub fn new(indexer: Arc<Indexer>, options: PageRankOptions) -> Self {
+        Self { indexer, options }
+    }
+
+    /// Recomputes PageRank and returns the number of indexed documents
+    /// it was stored for. Pages are matched to documents by their
+    /// normalized URL, `Document::link_url`. Linked pages that weren't
+    /// indexed are ranked but not stored.
+    pub fn run_once(&self) -> Result<usize, CrawlerError> {
+        let edges = LinkGraph::new(self.indexer.ctx().rocks_con.clone()).edges()?;
+        let ranks: HashMap<String, f64> = pagerank(&edges, &self.options);
+
+        let reader = IndexReader::new(self.indexer.ctx());
+        let mut doc_ranks: Vec<(u64, f32)> = vec![];
+        for (url, rank) in ranks {
+            if let Some(doc_id) = reader.linked_doc_id(&url).map_err(|err| CrawlerError::storage("pagerank", err))? {
+                doc_ranks.push((doc_id, rank as f32));
+            }
+        }
+
+        self.indexer.set_pageranks(&doc_ranks).map_err(|err| CrawlerError::storage("pagerank", err))?;
+        Ok(doc_ranks.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testkit::TestCtx;
+
+    use super::*;
+
+    fn edges(links: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        links.iter()
+            .map(|(source, targets)| (source.to_string(), targets.iter().map(|target| target.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_linked_pages_rank_higher() {
+        let ranks = pagerank(&edges(&[
+            ("a", &["hub", "a"]),
+            ("b", &["hub"]),
+            ("c", &["hub", "b"]),
+            ("hub", &["a"])
+        ]), &PageRankOptions::default());
+
+        assert_eq!(ranks.len(), 4);
+        assert!((ranks.values().sum::<f64>() - 4.0).abs() < 1e-6);
+        assert!(ranks["hub"] > ranks["a"]);
+        assert!(ranks["a"] > ranks["b"]);
+        assert!(ranks["b"] > ranks["c"]);
+    }
+
+    #[test]
+    fn test_dangling_pages_keep_ranks_normalized() {
+        let ranks = pagerank(&edges(&[("a", &["b", "c"])]), &PageRankOptions::default());
+        assert!((ranks.values().sum::<f64>() - 3.0).abs() < 1e-6);
+        assert!((ranks["b"] - ranks["c"]).abs() < 1e-9);
+        assert!(ranks["b"] > ranks["a"]);
+        assert!(pagerank(&[], &PageRankOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_job_stores_ranks_of_indexed_pages() {
+        let (ctx, indexer) = TestCtx::with("pagerank", |ctx| Arc::new(Indexer::new(ctx)));
+
+        let mut ids: Vec<u64> = vec![];
+        // the index keeps the URL a page was fetched from, links lead to it normalized
+        for (url, link_url) in [("https://a.com/", None), ("https://A.com/popular#top", Some("https://a.com/popular"))] {
+            let doc = indexer::Document { url: url.to_string(), link_url: link_url.map(str::to_string), ..indexer::Document::default() };
+            ids.push(indexer.create_index(&doc).unwrap());
+        }
+        let graph = LinkGraph::new(ctx.rocks_con.clone());
+        graph.set_outlinks("https://a.com/", &["https://a.com/popular".to_string(), "https://a.com/unindexed".to_string()]).unwrap();
+        graph.set_outlinks("https://a.com/popular", &["https://a.com/".to_string()]).unwrap();
+
+        let job = PageRankJob::new(indexer.clone(), PageRankOptions::default());
+        assert_eq!(job.run_once().unwrap(), 2);
+
+        let reader = IndexReader::new(&ctx);
+        let home: f32 = reader.pagerank(ids[0]).unwrap().unwrap();
+        let popular: f32 = reader.pagerank(ids[1]).unwrap().unwrap();
+        assert!(home > popular);
+    }
+}
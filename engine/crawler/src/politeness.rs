@@ -0,0 +1,216 @@
+//! Keeps the crawler from overloading the sites it visits.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant
+};
+
+/// Limits shared by every request of a crawl.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolitenessOptions {
+    /// Requests in flight over all hosts.
+    pub max_concurrency: usize,
+
+    /// Requests in flight to a single host.
+    pub max_per_host: usize,
+
+    /// Sustained request rate per host. A robots.txt `Crawl-delay`
+    /// lowers it further, never raises it.
+    pub requests_per_second: f64,
+
+    /// Requests a host may get back to back before the rate applies.
+    pub burst: u32
+}
+
+impl Default for PolitenessOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 16,
+            max_per_host: 2,
+            requests_per_second: 1.0,
+            burst: 1
+        }
+    }
+}
+
+/// Token bucket that lets the balance go negative: a caller takes its
+/// token right away and is told how long to wait for it, so concurrent
+/// callers queue up without retrying.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, now: Instant) -> Self {
+        Self {
+            capacity: capacity.max(1) as f64,
+            tokens: capacity.max(1) as f64,
+            last_refill: now
+        }
+    }
+
+    /// Takes one token at `rate` tokens per second and returns how long
+    /// the caller has to wait before using it.
+    fn reserve(&mut self, rate: f64, now: Instant) -> Duration {
+        let elapsed: f64 = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(self.capacity);
+        self.last_refill = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HostState {
+    in_flight: Arc<Semaphore>,
+    bucket: TokenBucket
+}
+
+/// Held while a request runs; dropping it frees the host and global slots.
+#[derive(Debug)]
+pub struct PolitePermit {
+    _host: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit
+}
+
+#[derive(Debug)]
+pub struct Politeness {
+    options: PolitenessOptions,
+    global: Arc<Semaphore>,
+    hosts: Mutex<HashMap<String, HostState>>
+}
+
+impl Politeness {
+    pub fn new(options: PolitenessOptions) -> Self {
+        Self {
+            options,
+            global: Arc::new(Semaphore::new(options.max_concurrency.max(1))),
+            hosts: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Waits until a request to `host` is allowed. `crawl_delay` is the
+    /// host's robots.txt `Crawl-delay`, if any.
+    pub async fn acquire(&self, host: &str, crawl_delay: Option<Duration>) -> PolitePermit {
+        let in_flight: Arc<Semaphore> = self.host_state(host, |state| state.in_flight.clone());
+
+        // the host slot comes first so one busy host can't hold global slots while it waits
+        let host_permit: OwnedSemaphorePermit = in_flight.acquire_owned().await.expect("semaphore closed");
+
+        let rate: f64 = self.rate(crawl_delay);
+        let wait: Duration = self.host_state(host, |state| state.bucket.reserve(rate, Instant::now()));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        let global_permit: OwnedSemaphorePermit = self.global.clone().acquire_owned().await.expect("semaphore closed");
+        PolitePermit {
+            _host: host_permit,
+            _global: global_permit
+        }
+    }
+
+    fn rate(&self, crawl_delay: Option<Duration>) -> f64 {
+        let rate: f64 = self.options.requests_per_second.max(f64::MIN_POSITIVE);
+        match crawl_delay.filter(|delay| !delay.is_zero()) {
+            Some(delay) => rate.min(1.0 / delay.as_secs_f64()),
+            None => rate
+        }
+    }
+
+    fn host_state<T>(&self, host: &str, f: impl FnOnce(&mut HostState) -> T) -> T {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state: &mut HostState = hosts.entry(host.to_string()).or_insert_with(|| HostState {
+            in_flight: Arc::new(Semaphore::new(self.options.max_per_host.max(1))),
+            bucket: TokenBucket::new(self.options.burst, Instant::now())
+        });
+        f(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket_spaces_requests() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, start);
+
+        assert_eq!(bucket.reserve(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(1.0, start), Duration::from_secs(1));
+        assert_eq!(bucket.reserve(1.0, start), Duration::from_secs(2));
+        assert_eq!(bucket.reserve(1.0, start + Duration::from_secs(10)), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crawl_delay_lowers_the_rate() {
+        let politeness = Politeness::new(PolitenessOptions { requests_per_second: 10.0, ..PolitenessOptions::default() });
+        let start = Instant::now();
+        for _ in 0..3 {
+            drop(politeness.acquire("example.com", Some(Duration::from_secs(2))).await);
+        }
+        assert!(start.elapsed() >= Duration::from_secs(4));
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            drop(politeness.acquire("other.com", None).await);
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// Highest number of `acquire`s held at once when requesting every host in `hosts`.
+    async fn peak_in_flight(politeness: Politeness, hosts: &[&'static str]) -> usize {
+        let politeness = Arc::new(politeness);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let mut tasks = vec![];
+        for &host in hosts {
+            let (politeness, in_flight, peak) = (politeness.clone(), in_flight.clone(), peak.clone());
+            tasks.push(tokio::spawn(async move {
+                let _permit = politeness.acquire(host, None).await;
+                peak.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_in_flight_caps() {
+        let options = PolitenessOptions {
+            max_concurrency: 3,
+            max_per_host: 2,
+            requests_per_second: 1000.0,
+            burst: 100
+        };
+
+        let same_host = peak_in_flight(Politeness::new(options), &["a.com"; 6]).await;
+        assert_eq!(same_host, 2);
+
+        let many_hosts = peak_in_flight(Politeness::new(options), &["a.com", "b.com", "c.com", "d.com", "e.com"]).await;
+        assert_eq!(many_hosts, 3);
+    }
+}
@@ -0,0 +1,307 @@
+//! What the crawler remembers about every page it fetched, so later
+//! crawls can ask "has it changed?" and revisit pages as often as they
+//! actually change.
+//!
+//! Key layout:
+//! - `fetch:{url}`: JSON `FetchMeta`
+//! - `due:{next fetch:020} {url}`: empty, one per page, so the pages due
+//!   at some time are a range scan away
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::CrawlerError, CrawlerOptions, JobRegistry};
+
+const DUE_PREFIX: &str = "due:";
+
+/// Revisit interval of a page fetched once.
+pub const DEFAULT_RECRAWL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Pages that change all the time are still revisited at most this often.
+pub const MIN_RECRAWL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Pages that never change are still revisited at least this often.
+pub const MAX_RECRAWL_INTERVAL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+fn fetch_key(url: &str) -> String {
+    format!("fetch:{}", url)
+}
+
+fn due_key(next_fetch: u64, url: &str) -> String {
+    format!("due:{:020} {}", next_fetch, url)
+}
+
+/// Seconds since 1970-01-01.
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
+
+/// FNV-1a of `body`. Unlike `DefaultHasher` it's the same across builds,
+/// so stored hashes stay comparable.
+pub fn content_hash(body: &[u8]) -> u64 {
+    body.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Last fetch of a page and how often it changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FetchMeta {
+    /// Seconds since 1970-01-01.
+    pub last_fetched: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<u64>,
+
+    /// Fetches so far, and how many of them found the page changed.
+    pub fetches: u32,
+    pub changes: u32,
+
+    /// Seconds between two visits, see `record`.
+    pub interval: u64,
+
+    /// Normalized URL the page's links are recorded under in the
+    /// `LinkGraph`, after redirects and `rel=canonical`; `None` when it
+    /// was a duplicate.
+    #[serde(default)]
+    pub source: Option<String>
+}
+
+impl FetchMeta {
+    /// Records a fetch at `now`. A page that changed is revisited twice
+    /// as soon from then on, one that didn't half as often.
+    pub fn record(&mut self, changed: bool, now: u64) {
+        let interval: u64 = match (self.fetches, changed) {
+            (0, _) => DEFAULT_RECRAWL_INTERVAL.as_secs(),
+            (_, true) => self.interval / 2,
+            (_, false) => self.interval.saturating_mul(2)
+        };
+        self.interval = interval.clamp(MIN_RECRAWL_INTERVAL.as_secs(), MAX_RECRAWL_INTERVAL.as_secs());
+
+        if changed && self.fetches > 0 {
+            self.changes += 1;
+        }
+        self.fetches += 1;
+        self.last_fetched = now;
+    }
+
+    /// Records a failed fetch at `now`. Every failure in a row doubles
+    /// the wait before the next try, so dead pages drift towards
+    /// `MAX_RECRAWL_INTERVAL`.
+    pub fn record_failure(&mut self, now: u64) {
+        self.interval = self.interval.saturating_mul(2).clamp(MIN_RECRAWL_INTERVAL.as_secs(), MAX_RECRAWL_INTERVAL.as_secs());
+        self.last_fetched = now;
+    }
+
+    pub fn next_fetch(&self) -> u64 {
+        self.last_fetched.saturating_add(self.interval)
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        self.next_fetch() <= now
+    }
+}
+
+/// `FetchMeta` of every fetched page.
+#[derive(Debug, Clone)]
+pub struct FetchMetaStore {
+    db: Arc<rocksdb::DB>
+}
+
+impl FetchMetaStore {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self { db }
+    }
+
+    pub fn get(&self, url: &str) -> Result<Option<FetchMeta>, CrawlerError> {
+        let value: Option<Vec<u8>> = self.db.get(fetch_key(url)).map_err(|err| CrawlerError::storage(fetch_key(url), err))?;
+        value.map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .map_err(|err| CrawlerError::storage(fetch_key(url), err))
+    }
+
+    /// Stores `meta` and moves the page's `due:` entry to its next fetch.
+    pub fn put(&self, url: &str, meta: &FetchMeta) -> Result<(), CrawlerError> {
+        let key: String = fetch_key(url);
+        let value: Vec<u8> = serde_json::to_vec(meta).map_err(|err| CrawlerError::storage(&key, err))?;
+
+        let mut batch = rocksdb::WriteBatch::default();
+        if let Some(previous) = self.get(url)? {
+            batch.delete(due_key(previous.next_fetch(), url));
+        }
+        batch.put(due_key(meta.next_fetch(), url), b"");
+        batch.put(&key, value);
+        self.db.write(batch).map_err(|err| CrawlerError::storage(&key, err))
+    }
+
+    /// Pages due for a revisit at `now`, most overdue first.
+    pub fn due(&self, now: u64) -> Result<Vec<String>, CrawlerError> {
+        let mut due: Vec<String> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(DUE_PREFIX.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, _) = key_value.map_err(|err| CrawlerError::storage("due pages", err))?;
+            let Some(entry) = key.strip_prefix(DUE_PREFIX.as_bytes()) else {
+                break;
+            };
+            let entry: String = String::from_utf8_lossy(entry).into_owned();
+            let Some((next_fetch, url)) = entry.split_once(' ') else {
+                continue;
+            };
+            if next_fetch.parse::<u64>().map_or(true, |next_fetch| next_fetch > now) {
+                break;
+            }
+            due.push(url.to_string());
+        }
+        Ok(due)
+    }
+}
+
+/// Starts a crawl job for every host with pages due for a revisit.
+#[derive(Clone)]
+pub struct RecrawlScheduler {
+    jobs: JobRegistry,
+    store: FetchMetaStore
+}
+
+impl RecrawlScheduler {
+    pub fn new(jobs: JobRegistry, db: Arc<rocksdb::DB>) -> Self {
+        Self {
+            jobs,
+            store: FetchMetaStore::new(db)
+        }
+    }
+
+    /// Starts the recrawl jobs due at `now` and returns their ids. Hosts
+    /// a job is still busy with are left for the next round, and so are
+    /// jobs that fail to start.
+    pub fn run_once(&self, now: u64) -> Result<Vec<u64>, CrawlerError> {
+        let busy: Vec<String> = self.jobs.list().into_iter()
+            .filter(|status| !status.state.is_finished())
+            .filter_map(|status| host_of(&status.seed_url))
+            .collect();
+
+        // the most overdue page of each host seeds its job, which queues the others
+        let mut seeds: BTreeMap<String, String> = BTreeMap::new();
+        for url in self.store.due(now)? {
+            if let Some(host) = host_of(&url).filter(|host| !busy.contains(host)) {
+                seeds.entry(host).or_insert(url);
+            }
+        }
+
+        let mut ids: Vec<u64> = vec![];
+        for seed_url in seeds.into_values() {
+            let started = self.jobs.start(CrawlerOptions {
+                seed_url: seed_url.clone(),
+                max_depth: 0,
+                recrawl: true,
+                use_sitemaps: false,
+                ..CrawlerOptions::default()
+            });
+            match started {
+                Ok(id) => ids.push(id),
+                Err(err) => eprintln!("Failed to start the recrawl of {}: {}", seed_url, err)
+            }
+        }
+        Ok(ids)
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use testkit::TestCtx;
+
+    use super::*;
+
+    #[test]
+    fn test_interval_adapts_to_changes() {
+        let mut meta = FetchMeta::default();
+        meta.record(true, 1000);
+        assert_eq!(meta.interval, DEFAULT_RECRAWL_INTERVAL.as_secs());
+        assert_eq!((meta.fetches, meta.changes), (1, 0));
+
+        meta.record(false, 2000);
+        assert_eq!(meta.interval, 2 * DEFAULT_RECRAWL_INTERVAL.as_secs());
+        meta.record(true, 3000);
+        meta.record(true, 4000);
+        assert_eq!(meta.interval, DEFAULT_RECRAWL_INTERVAL.as_secs() / 2);
+        assert_eq!((meta.fetches, meta.changes), (4, 2));
+
+        for _ in 0..20 {
+            meta.record(true, 5000);
+        }
+        assert_eq!(meta.interval, MIN_RECRAWL_INTERVAL.as_secs());
+        assert!(meta.is_due(5000 + MIN_RECRAWL_INTERVAL.as_secs()));
+        assert!(!meta.is_due(5000));
+    }
+
+    #[test]
+    fn test_failures_back_off() {
+        let mut meta = FetchMeta::default();
+        meta.record_failure(1000);
+        assert_eq!((meta.last_fetched, meta.interval), (1000, MIN_RECRAWL_INTERVAL.as_secs()));
+        meta.record_failure(2000);
+        assert_eq!(meta.interval, 2 * MIN_RECRAWL_INTERVAL.as_secs());
+        for _ in 0..20 {
+            meta.record_failure(3000);
+        }
+        assert_eq!(meta.interval, MAX_RECRAWL_INTERVAL.as_secs());
+        assert_eq!(meta.fetches, 0);
+    }
+
+    #[test]
+    fn test_content_hash_is_stable() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(content_hash(b"<p>one</p>"), content_hash(b"<p>two</p>"));
+    }
+
+    #[test]
+    fn test_store_lists_due_pages() {
+        let ctx = TestCtx::new("recrawl-due");
+        let store = FetchMetaStore::new(ctx.rocks_con.clone());
+
+        let day: u64 = DEFAULT_RECRAWL_INTERVAL.as_secs();
+        for (url, fetched) in [("https://a.com/old", 0), ("https://a.com/older", 0), ("https://a.com/new", 5 * day)] {
+            let mut meta = FetchMeta::default();
+            meta.record(true, fetched);
+            store.put(url, &meta).unwrap();
+        }
+        let mut older = store.get("https://a.com/older").unwrap().unwrap();
+        older.last_fetched = 0;
+        older.interval = day / 2;
+        store.put("https://a.com/older", &older).unwrap();
+
+        assert_eq!(store.due(2 * day).unwrap(), vec!["https://a.com/older", "https://a.com/old"]);
+        assert_eq!(store.get("https://a.com/missing").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_starts_a_job_per_host() {
+        let (ctx, jobs) = TestCtx::with("recrawl-scheduler", |ctx| JobRegistry::new(Arc::new(indexer::Indexer::new(ctx))));
+        let scheduler = RecrawlScheduler::new(jobs.clone(), ctx.rocks_con.clone());
+
+        let store = FetchMetaStore::new(ctx.rocks_con.clone());
+        for url in ["http://127.0.0.1:9/a", "http://127.0.0.1:9/b", "http://localhost:9/"] {
+            let mut meta = FetchMeta::default();
+            meta.record(true, 0);
+            store.put(url, &meta).unwrap();
+        }
+
+        let now: u64 = DEFAULT_RECRAWL_INTERVAL.as_secs();
+        let ids = scheduler.run_once(now).unwrap();
+        assert_eq!(ids.len(), 2);
+        let seeds: Vec<String> = jobs.list().into_iter().map(|status| status.seed_url).collect();
+        assert_eq!(seeds, vec!["http://127.0.0.1:9/a", "http://localhost:9/"]);
+
+        // both hosts are busy with their jobs
+        assert!(scheduler.run_once(now).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+//! robots.txt (RFC 9309) parsing and a per-host cache.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration
+};
+
+use tokio::sync::OnceCell;
+
+use crate::{
+    errors::CrawlerError,
+    fetch::{FetchOptions, read_body, send_with_retries}
+};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+
+    /// Path pattern; `*` matches any run of characters, a trailing `$`
+    /// anchors the pattern at the end of the path.
+    pattern: String
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Group {
+    /// Lowercased product tokens from the `User-agent` lines.
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>
+}
+
+/// A parsed robots.txt.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RobotsTxt {
+    groups: Vec<Group>,
+    sitemaps: Vec<String>,
+
+    /// Set when robots.txt couldn't be fetched because of a server
+    /// error, in which case nothing may be crawled.
+    disallow_all: bool
+}
+
+impl RobotsTxt {
+    /// Allows everything, e.g. when a host has no robots.txt.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Forbids everything, e.g. when robots.txt answered with a 5xx.
+    pub fn disallow_all() -> Self {
+        Self { disallow_all: true, ..Self::default() }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut robots = RobotsTxt::default();
+        let mut group = Group::default();
+
+        // consecutive User-agent lines share the rules that follow them
+        let mut in_agent_lines: bool = false;
+
+        for line in content.lines() {
+            let line: &str = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_lowercase(), value.trim());
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_agent_lines && !group.agents.is_empty() {
+                        robots.groups.push(std::mem::take(&mut group));
+                    }
+                    group.agents.push(value.to_lowercase());
+                    in_agent_lines = true;
+                },
+                "allow" | "disallow" => {
+                    in_agent_lines = false;
+                    // an empty Disallow allows everything; an empty Allow means nothing
+                    if !value.is_empty() {
+                        group.rules.push(Rule { allow: key == "allow", pattern: value.to_string() });
+                    }
+                },
+                "crawl-delay" => {
+                    in_agent_lines = false;
+                    group.crawl_delay = value.parse::<f64>().ok()
+                        .filter(|delay| delay.is_finite() && *delay >= 0.0)
+                        .map(Duration::from_secs_f64);
+                },
+                "sitemap" => robots.sitemaps.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        if !group.agents.is_empty() {
+            robots.groups.push(group);
+        }
+        robots
+    }
+
+    /// The group that applies to `user_agent`: the one naming the longest
+    /// matching product token, or the `*` group.
+    fn group_for(&self, user_agent: &str) -> Option<&Group> {
+        let product: String = product_token(user_agent);
+
+        self.groups.iter()
+            .filter_map(|group| {
+                group.agents.iter()
+                    .filter(|agent| *agent != "*" && product.starts_with(agent.as_str()))
+                    .map(String::len)
+                    .max()
+                    .map(|length| (length, group))
+            })
+            .max_by_key(|(length, _)| *length)
+            .map(|(_, group)| group)
+            .or_else(|| self.groups.iter().find(|group| group.agents.iter().any(|agent| agent == "*")))
+    }
+
+    /// Whether `user_agent` may fetch `path` (path plus query string).
+    /// The longest matching rule wins; on a tie `Allow` does.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        if self.disallow_all {
+            return false;
+        }
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        let Some(group) = self.group_for(user_agent) else {
+            return true;
+        };
+
+        group.rules.iter()
+            .filter(|rule| pattern_matches(&rule.pattern, path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.group_for(user_agent).and_then(|group| group.crawl_delay)
+    }
+
+    /// Sitemap URLs listed in the file, for any user agent.
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+}
+
+/// `SpyBot/0.1 (+info)` -> `spybot`
+pub(crate) fn product_token(user_agent: &str) -> String {
+    user_agent.split(['/', ' ']).next().unwrap_or_default().to_lowercase()
+}
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false)
+    };
+
+    let mut parts = pattern.split('*');
+    let first: &str = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let is_last: bool = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// robots.txt of an origin as fetched.
+#[derive(Debug)]
+struct Fetched {
+    robots: Arc<RobotsTxt>,
+
+    /// Why robots.txt couldn't be fetched, in which case `robots`
+    /// disallows everything.
+    error: Option<CrawlerError>
+}
+
+/// Fetches robots.txt at most once per origin and keeps it for the
+/// lifetime of the cache.
+#[derive(Debug)]
+pub struct RobotsCache {
+    client: reqwest::Client,
+    user_agent: String,
+    fetch: FetchOptions,
+    entries: Mutex<HashMap<String, Arc<OnceCell<Arc<Fetched>>>>>
+}
+
+impl RobotsCache {
+    /// A cache fetching with `client`, the retries and size limit of `fetch`.
+    pub fn new(client: reqwest::Client, user_agent: impl Into<String>, fetch: FetchOptions) -> Self {
+        Self {
+            client,
+            user_agent: user_agent.into(),
+            fetch,
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// robots.txt of the origin `url` belongs to.
+    pub async fn get(&self, url: &url::Url) -> Arc<RobotsTxt> {
+        self.fetched(url).await.robots.clone()
+    }
+
+    /// Whether the crawler may fetch `url`. Anything but http(s) is refused.
+    pub async fn is_allowed(&self, url: &str) -> bool {
+        self.check(url).await.is_ok()
+    }
+
+    /// `is_allowed`, with the reason when it isn't: robots.txt disallows
+    /// `url`, or robots.txt couldn't be fetched, which is reported as the
+    /// error of `url` itself.
+    pub async fn check(&self, url: &str) -> Result<(), CrawlerError> {
+        let disallowed = || CrawlerError::RobotsDisallowed { url: url.to_string() };
+        let Ok(parsed) = url::Url::parse(url) else {
+            return Err(disallowed());
+        };
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(disallowed());
+        }
+
+        let fetched: Arc<Fetched> = self.fetched(&parsed).await;
+        if let Some(err) = &fetched.error {
+            return Err(err.for_url(url));
+        }
+        let path: String = match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string()
+        };
+        if fetched.robots.is_allowed(&self.user_agent, &path) {
+            Ok(())
+        } else {
+            Err(disallowed())
+        }
+    }
+
+    pub async fn crawl_delay(&self, url: &url::Url) -> Option<Duration> {
+        self.get(url).await.crawl_delay(&self.user_agent)
+    }
+
+    async fn fetched(&self, url: &url::Url) -> Arc<Fetched> {
+        let origin: String = url.origin().ascii_serialization();
+        let cell: Arc<OnceCell<Arc<Fetched>>> = self.entries.lock().unwrap()
+            .entry(origin.clone())
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| async { Arc::new(self.fetch(&origin).await) }).await.clone()
+    }
+
+    async fn fetch(&self, origin: &str) -> Fetched {
+        let robots_url: String = format!("{}/robots.txt", origin);
+        let failed = |err: CrawlerError| {
+            eprintln!("Couldn't fetch robots.txt of {}: {}", origin, err);
+            Fetched { robots: Arc::new(RobotsTxt::disallow_all()), error: Some(err) }
+        };
+        let fetched = |robots: RobotsTxt| Fetched { robots: Arc::new(robots), error: None };
+
+        let response = match send_with_retries(self.client.get(&robots_url), &robots_url, &self.fetch).await {
+            Ok(response) => response,
+            Err(err) => return failed(err)
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            match read_body(response, &robots_url, self.fetch.max_body_bytes).await {
+                Ok(body) => fetched(RobotsTxt::parse(&String::from_utf8_lossy(&body))),
+                Err(err) => failed(err)
+            }
+        } else if status.is_client_error() {
+            // no robots.txt (404, 403...) means no restrictions
+            fetched(RobotsTxt::allow_all())
+        } else {
+            failed(CrawlerError::Status { url: robots_url, status: status.as_u16() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+        # comments are ignored
+        User-agent: *
+        Disallow: /private/
+        Allow: /private/public$
+        Crawl-delay: 2
+
+        User-agent: spybot
+        User-agent: otherbot
+        Disallow: /search
+        Disallow: /*.pdf$
+        Crawl-delay: 0.5
+
+        Sitemap: https://example.com/sitemap.xml
+    ";
+
+    #[test]
+    fn test_default_group() {
+        let robots = RobotsTxt::parse(ROBOTS);
+        assert!(!robots.is_allowed("SomeBot/1.0", "/private/page"));
+        assert!(robots.is_allowed("SomeBot/1.0", "/private/public"));
+        assert!(!robots.is_allowed("SomeBot/1.0", "/private/public/more"));
+        assert!(robots.is_allowed("SomeBot/1.0", "/search"));
+        assert_eq!(robots.crawl_delay("SomeBot/1.0"), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_specific_group_replaces_default() {
+        let robots = RobotsTxt::parse(ROBOTS);
+        let agent = "SpyBot/0.1 (+crawler)";
+        assert!(robots.is_allowed(agent, "/private/page"));
+        assert!(!robots.is_allowed(agent, "/search?q=python"));
+        assert!(!robots.is_allowed(agent, "/docs/manual.pdf"));
+        assert!(robots.is_allowed(agent, "/docs/manual.pdf.html"));
+        assert_eq!(robots.crawl_delay(agent), Some(Duration::from_millis(500)));
+        assert_eq!(robots.sitemaps(), ["https://example.com/sitemap.xml"]);
+    }
+
+    #[test]
+    fn test_longest_rule_wins() {
+        let robots = RobotsTxt::parse("User-agent: *\nDisallow: /docs\nAllow: /docs/3/\nDisallow: /docs/3/internal");
+        assert!(!robots.is_allowed("bot", "/docs/2/"));
+        assert!(robots.is_allowed("bot", "/docs/3/tutorial"));
+        assert!(!robots.is_allowed("bot", "/docs/3/internal/x"));
+        assert!(RobotsTxt::parse("User-agent: *\nDisallow:").is_allowed("bot", "/anything"));
+    }
+
+    #[tokio::test]
+    async fn test_cache_fetches_robots_once_per_host() {
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = requests.clone();
+        let origin = testkit::serve(move |request| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            match request.url() {
+                "/robots.txt" => tiny_http::Response::from_string("User-agent: *\nDisallow: /private").boxed(),
+                _ => tiny_http::Response::from_string("").with_status_code(404).boxed()
+            }
+        });
+
+        let cache = RobotsCache::new(reqwest::Client::new(), "SpyBot/0.1", FetchOptions::default());
+        assert!(cache.is_allowed(&format!("{}/docs", origin)).await);
+        assert!(!cache.is_allowed(&format!("{}/private/x", origin)).await);
+        assert!(!cache.is_allowed("ftp://example.com/file").await);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,187 @@
+//! Which discovered URLs belong to a crawl.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex
+};
+
+use regex::Regex;
+
+/// How far from the seed's host a crawl may wander.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScopeMode {
+    /// Only the seed's exact host.
+    #[default]
+    SameHost,
+
+    /// The seed's registrable domain and all its subdomains.
+    SameDomain,
+
+    Any
+}
+
+#[derive(Debug, Clone)]
+pub struct ScopeOptions {
+    pub mode: ScopeMode,
+
+    /// When not empty, a URL must match at least one of these.
+    pub include: Vec<Regex>,
+
+    /// A URL matching any of these is skipped, even if included.
+    pub exclude: Vec<Regex>,
+
+    pub allowed_schemes: Vec<String>,
+
+    /// Pages fetched from a single host at most.
+    pub max_pages_per_host: Option<usize>
+}
+
+impl Default for ScopeOptions {
+    fn default() -> Self {
+        Self {
+            mode: ScopeMode::default(),
+            include: vec![],
+            exclude: vec![],
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            max_pages_per_host: None
+        }
+    }
+}
+
+/// `ScopeOptions` applied to one crawl.
+#[derive(Debug)]
+pub struct Scope {
+    options: ScopeOptions,
+    seed_host: String,
+    pages_per_host: Mutex<HashMap<String, usize>>
+}
+
+impl Scope {
+    pub fn new(options: ScopeOptions, seed_url: &str) -> Self {
+        let seed_host: String = url::Url::parse(seed_url).ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+
+        Self {
+            options,
+            seed_host,
+            pages_per_host: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Whether `url` is inside the crawl. Doesn't count against
+    /// `max_pages_per_host`, see `claim_page`.
+    pub fn admits(&self, url: &str) -> bool {
+        let Ok(parsed) = url::Url::parse(url) else {
+            return false;
+        };
+
+        if !self.options.allowed_schemes.iter().any(|scheme| scheme.eq_ignore_ascii_case(parsed.scheme())) {
+            return false;
+        }
+
+        let host: String = parsed.host_str().unwrap_or_default().to_lowercase();
+        let in_mode: bool = match self.options.mode {
+            ScopeMode::SameHost => host == self.seed_host,
+            ScopeMode::SameDomain => registrable_domain(&host) == registrable_domain(&self.seed_host),
+            ScopeMode::Any => true
+        };
+        if !in_mode {
+            return false;
+        }
+
+        let included: bool = self.options.include.is_empty() || self.options.include.iter().any(|re| re.is_match(url));
+        included && !self.options.exclude.iter().any(|re| re.is_match(url))
+    }
+
+    /// Counts `url` against its host's page budget. Returns `false` when
+    /// the host has used it up.
+    pub fn claim_page(&self, url: &str) -> bool {
+        let Some(max_pages) = self.options.max_pages_per_host else {
+            return true;
+        };
+        let host: String = url::Url::parse(url).ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+
+        let mut pages_per_host = self.pages_per_host.lock().unwrap();
+        let pages: &mut usize = pages_per_host.entry(host).or_default();
+        if *pages >= max_pages {
+            return false;
+        }
+        *pages += 1;
+        true
+    }
+}
+
+/// Best guess at the domain someone registered, without a public suffix
+/// list: the last two labels, or three when the second to last looks like
+/// a second-level suffix (`example.co.uk`).
+fn registrable_domain(host: &str) -> &str {
+    let labels: Vec<&str> = host.rsplit('.').collect();
+    let keep: usize = match labels.as_slice() {
+        [tld, second, _, ..] if tld.len() == 2 && matches!(*second, "co" | "com" | "org" | "net" | "ac" | "gov" | "edu") => 3,
+        _ => 2
+    };
+
+    if labels.len() <= keep {
+        return host;
+    }
+    let cut: usize = labels[..keep].iter().map(|label| label.len() + 1).sum::<usize>();
+    &host[host.len() - cut + 1..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(options: ScopeOptions) -> Scope {
+        Scope::new(options, "https://docs.python.org/3/")
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        assert_eq!(registrable_domain("docs.python.org"), "python.org");
+        assert_eq!(registrable_domain("python.org"), "python.org");
+        assert_eq!(registrable_domain("www.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(registrable_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn test_scope_modes() {
+        let same_host = scope(ScopeOptions::default());
+        assert!(same_host.admits("https://docs.python.org/2/library/"));
+        assert!(!same_host.admits("https://www.python.org/"));
+
+        let same_domain = scope(ScopeOptions { mode: ScopeMode::SameDomain, ..ScopeOptions::default() });
+        assert!(same_domain.admits("https://www.python.org/"));
+        assert!(!same_domain.admits("https://pypi.org/"));
+
+        let any = scope(ScopeOptions { mode: ScopeMode::Any, ..ScopeOptions::default() });
+        assert!(any.admits("https://pypi.org/"));
+        assert!(!any.admits("mailto:docs@python.org"));
+        assert!(!any.admits("javascript:void(0)"));
+    }
+
+    #[test]
+    fn test_include_and_exclude_patterns() {
+        let docs_tree = scope(ScopeOptions {
+            include: vec![Regex::new(r"^https://docs\.python\.org/3/").unwrap()],
+            exclude: vec![Regex::new(r"/whatsnew/").unwrap(), Regex::new(r"\.zip$").unwrap()],
+            ..ScopeOptions::default()
+        });
+        assert!(docs_tree.admits("https://docs.python.org/3/tutorial/index.html"));
+        assert!(!docs_tree.admits("https://docs.python.org/2/tutorial/index.html"));
+        assert!(!docs_tree.admits("https://docs.python.org/3/whatsnew/3.12.html"));
+        assert!(!docs_tree.admits("https://docs.python.org/3/archives/python-docs.zip"));
+    }
+
+    #[test]
+    fn test_max_pages_per_host() {
+        let limited = scope(ScopeOptions { mode: ScopeMode::Any, max_pages_per_host: Some(2), ..ScopeOptions::default() });
+        assert!(limited.claim_page("https://docs.python.org/3/a"));
+        assert!(limited.claim_page("https://docs.python.org/3/b"));
+        assert!(!limited.claim_page("https://docs.python.org/3/c"));
+        assert!(limited.claim_page("https://pypi.org/"));
+    }
+}
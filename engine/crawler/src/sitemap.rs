@@ -0,0 +1,229 @@
+//! sitemap.xml support: the pages a site lists for crawlers, with how
+//! important it says they are and when they last changed.
+
+use std::io::Read;
+
+use quick_xml::events::Event;
+
+use crate::{
+    errors::CrawlerError,
+    fetch::{FetchOptions, read_body, send_with_retries}
+};
+
+/// Largest sitemap accepted, after inflating; the limit sitemaps.org sets.
+const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+
+/// `<priority>` of URLs that don't give one.
+const DEFAULT_PRIORITY: f64 = 0.5;
+
+/// Most a recent `<lastmod>` adds to a URL's value.
+const RECENCY_BONUS: f64 = 0.25;
+
+/// A `<url>` of a sitemap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapUrl {
+    pub loc: String,
+
+    /// W3C datetime, e.g. `2024-05-01` or `2024-05-01T10:00:00+00:00`.
+    pub lastmod: Option<String>,
+
+    /// Importance relative to the site's other pages, in `[0, 1]`.
+    pub priority: Option<f64>
+}
+
+impl SitemapUrl {
+    /// Frontier value of the URL: its priority, raised for pages changed
+    /// in the last year, the more the more recent.
+    pub fn value(&self, today: i64) -> f64 {
+        let priority: f64 = self.priority.unwrap_or(DEFAULT_PRIORITY).clamp(0.0, 1.0);
+        let recency: f64 = self.lastmod.as_deref()
+            .and_then(days_since_epoch)
+            .map(|day| 1.0 - ((today - day).max(0) as f64 / 365.0).min(1.0))
+            .unwrap_or(0.0);
+        (priority + RECENCY_BONUS * recency).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    /// `<urlset>`: pages.
+    Urls(Vec<SitemapUrl>),
+
+    /// `<sitemapindex>`: locations of more sitemaps.
+    Index(Vec<String>)
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Loc,
+    Lastmod,
+    Priority
+}
+
+/// Parses a `<urlset>` or `<sitemapindex>` document.
+pub fn parse_sitemap(xml: &str) -> Result<Sitemap, quick_xml::Error> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut is_index: bool = false;
+    let mut urls: Vec<SitemapUrl> = vec![];
+    let mut current: Option<SitemapUrl> = None;
+    let mut field: Option<Field> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) => match tag.local_name().as_ref() {
+                b"sitemapindex" => is_index = true,
+                b"url" | b"sitemap" => current = Some(SitemapUrl { loc: String::new(), lastmod: None, priority: None }),
+                b"loc" => field = Some(Field::Loc),
+                b"lastmod" => field = Some(Field::Lastmod),
+                b"priority" => field = Some(Field::Priority),
+                _ => field = None
+            },
+            Event::Text(text) => set_field(&mut current, field, &text.unescape()?),
+            Event::CData(data) => set_field(&mut current, field, &String::from_utf8_lossy(&data)),
+            Event::End(tag) => match tag.local_name().as_ref() {
+                b"url" | b"sitemap" => {
+                    if let Some(url) = current.take().filter(|url| !url.loc.is_empty()) {
+                        urls.push(url);
+                    }
+                }
+                _ => field = None
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if is_index {
+        Ok(Sitemap::Index(urls.into_iter().map(|url| url.loc).collect()))
+    } else {
+        Ok(Sitemap::Urls(urls))
+    }
+}
+
+fn set_field(current: &mut Option<SitemapUrl>, field: Option<Field>, text: &str) {
+    let (Some(url), Some(field)) = (current.as_mut(), field) else {
+        return;
+    };
+    let text: &str = text.trim();
+
+    match field {
+        Field::Loc => url.loc.push_str(text),
+        Field::Lastmod => url.lastmod = Some(text.to_string()),
+        Field::Priority => url.priority = text.parse::<f64>().ok()
+    }
+}
+
+/// Text of the sitemap body fetched from `url`, inflated first when
+/// gzipped (`.xml.gz`).
+pub fn decode_sitemap(url: &str, body: &[u8]) -> Result<String, CrawlerError> {
+    let mut xml = String::new();
+    let read = if body.starts_with(&[0x1f, 0x8b]) {
+        flate2::read::GzDecoder::new(body).take(MAX_SITEMAP_BYTES + 1).read_to_string(&mut xml)
+    } else {
+        body.take(MAX_SITEMAP_BYTES + 1).read_to_string(&mut xml)
+    };
+    let read: usize = read.map_err(|err| CrawlerError::Parse { url: url.to_string(), message: err.to_string() })?;
+
+    if read as u64 > MAX_SITEMAP_BYTES {
+        return Err(CrawlerError::TooLarge { url: url.to_string(), limit: MAX_SITEMAP_BYTES });
+    }
+    Ok(xml)
+}
+
+/// Fetches and parses the sitemap at `url`, with the retries and size
+/// limit of `options`.
+pub async fn fetch_sitemap(client: &reqwest::Client, url: &str, options: &FetchOptions) -> Result<Sitemap, CrawlerError> {
+    let resp = send_with_retries(client.get(url), url, options).await?;
+
+    if !resp.status().is_success() {
+        return Err(CrawlerError::Status { url: url.to_string(), status: resp.status().as_u16() });
+    }
+
+    let body: Vec<u8> = read_body(resp, url, options.max_body_bytes).await?;
+    parse_sitemap(&decode_sitemap(url, &body)?)
+        .map_err(|err| CrawlerError::Parse { url: url.to_string(), message: err.to_string() })
+}
+
+/// Days from 1970-01-01 to the date a W3C datetime starts with.
+pub(crate) fn days_since_epoch(datetime: &str) -> Option<i64> {
+    let mut parts = datetime.get(..10)?.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok().filter(|month| (1..=12).contains(month))?;
+    let day: i64 = parts.next()?.parse().ok().filter(|day| (1..=31).contains(day))?;
+
+    // civil calendar to day count, with years starting in March
+    let year: i64 = if month <= 2 { year - 1 } else { year };
+    let era: i64 = year.div_euclid(400);
+    let year_of_era: i64 = year - era * 400;
+    let day_of_year: i64 = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era: i64 = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <url>
+                <loc>https://docs.python.org/3/</loc>
+                <lastmod>2024-05-01T10:00:00+00:00</lastmod>
+                <priority>0.9</priority>
+            </url>
+            <url><loc><![CDATA[https://docs.python.org/3/search.html?q=a&b=c]]></loc></url>
+            <url><loc>https://docs.python.org/3/genindex.html?a=1&amp;b=2</loc></url>
+        </urlset>"#;
+
+    #[test]
+    fn test_parse_urlset() {
+        let Sitemap::Urls(urls) = parse_sitemap(URLSET).unwrap() else {
+            panic!("expected a urlset");
+        };
+        assert_eq!(urls.len(), 3);
+        assert_eq!(urls[0], SitemapUrl {
+            loc: "https://docs.python.org/3/".to_string(),
+            lastmod: Some("2024-05-01T10:00:00+00:00".to_string()),
+            priority: Some(0.9)
+        });
+        assert_eq!(urls[1].loc, "https://docs.python.org/3/search.html?q=a&b=c");
+        assert_eq!(urls[2].loc, "https://docs.python.org/3/genindex.html?a=1&b=2");
+    }
+
+    #[test]
+    fn test_parse_index_and_gzip() {
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <sitemap><loc>https://example.com/pages.xml.gz</loc><lastmod>2024-01-01</lastmod></sitemap>
+        </sitemapindex>"#;
+
+        let mut gzipped = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzipped.write_all(index.as_bytes()).unwrap();
+        let body: Vec<u8> = gzipped.finish().unwrap();
+
+        assert_eq!(parse_sitemap(&decode_sitemap("https://example.com/sitemap.xml.gz", &body).unwrap()).unwrap(),
+            Sitemap::Index(vec!["https://example.com/pages.xml.gz".to_string()]));
+        assert_eq!(decode_sitemap("https://example.com/sitemap.xml", index.as_bytes()).unwrap(), index);
+    }
+
+    #[test]
+    fn test_value_from_priority_and_lastmod() {
+        assert_eq!(days_since_epoch("1970-01-01"), Some(0));
+        assert_eq!(days_since_epoch("2024-03-01T00:00:00Z"), Some(19_783));
+        assert_eq!(days_since_epoch("yesterday"), None);
+
+        let today: i64 = 19_783;
+        let url = |lastmod: Option<&str>, priority: Option<f64>| SitemapUrl {
+            loc: String::new(),
+            lastmod: lastmod.map(str::to_string),
+            priority
+        };
+        assert_eq!(url(None, None).value(today), 0.5);
+        assert!((url(Some("2024-03-01"), Some(0.6)).value(today) - 0.85).abs() < 1e-9);
+        assert!(url(Some("2024-02-01"), None).value(today) > url(Some("2023-06-01"), None).value(today));
+        assert_eq!(url(Some("2010-01-01"), Some(0.3)).value(today), 0.3);
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum IndexerError {
+    Storage(rocksdb::Error),
+    Serialization(serde_json::Error),
+
+    /// The document asked not to be indexed, see `Document::noindex`.
+    NoIndex(String)
+}
+
+impl fmt::Display for IndexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexerError::Storage(err) => write!(f, "index storage error: {}", err),
+            IndexerError::Serialization(err) => write!(f, "index serialization error: {}", err),
+            IndexerError::NoIndex(url) => write!(f, "{} asks not to be indexed", url)
+        }
+    }
+}
+
+impl std::error::Error for IndexerError {}
+
+impl From<rocksdb::Error> for IndexerError {
+    fn from(err: rocksdb::Error) -> Self {
+        IndexerError::Storage(err)
+    }
+}
+
+impl From<serde_json::Error> for IndexerError {
+    fn from(err: serde_json::Error) -> Self {
+        IndexerError::Serialization(err)
+    }
+}
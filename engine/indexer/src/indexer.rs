@@ -1,9 +1,486 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashSet
+    },
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use context::CTX;
+
+use crate::{
+    errors::IndexerError,
+    keys,
+    CollectionTotals,
+    IndexReader,
+    Document,
+    DocumentSource,
+    Field,
+    FieldCounts,
+    Posting,
+    StoredDocument
+};
+
+/// Position gap inserted between fields and between the values of a
+/// multi-valued field, so phrases never match across them.
+const FIELD_POSITION_GAP: usize = 100;
+
 #[derive(Debug, Clone)]
 pub struct Indexer {
+    ctx: Arc<CTX>,
+
+    /// Serializes doc id allocation and posting writes between crawler tasks.
+    write_lock: Arc<Mutex<()>>
 }
 
 impl Indexer {
-    pub fn create_index(&self) {
+    pub fn new(ctx: Arc<CTX>) -> Self {
+        Self {
+            ctx,
+            write_lock: Arc::new(Mutex::new(()))
+        }
+    }
+
+    pub fn ctx(&self) -> &CTX {
+        &self.ctx
+    }
+
+    /// Tokenizes every field of `doc` and writes a posting for each of its terms.
+    /// Indexing a URL again replaces its previous postings. The anchor text
+    /// other pages link to the document with is stored under the link
+    /// target's URL, `doc.link_url` or else `doc.url`, and is indexed along
+    /// with `doc.anchor_text`.
+    /// Returns the id the document was stored under.
+    ///
+    /// `noindex` documents are refused with `IndexerError::NoIndex`, and an
+    /// earlier version of the URL is removed from the index.
+    pub fn create_index(&self, doc: &Document) -> Result<u64, IndexerError> {
+        if doc.noindex {
+            self.remove(&doc.url)?;
+            return Err(IndexerError::NoIndex(doc.url.clone()));
+        }
+
+        let link_url: &str = doc.link_url.as_deref().unwrap_or(&doc.url);
+        let mut anchor_text: Vec<String> = doc.anchor_text.clone();
+        anchor_text.extend(IndexReader::new(&self.ctx).anchor_texts(link_url)?);
+        let indexed = Document { anchor_text, ..doc.clone() };
+        let tokens: Vec<(Field, wp::Token)> = Self::tokenize_fields(&indexed);
 
+        let mut postings: BTreeMap<String, Posting> = BTreeMap::new();
+        let mut field_lengths: FieldCounts = [0; Field::COUNT];
+
+        let _guard = self.write_lock.lock().unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        let doc_id: u64 = self.doc_id_for(&doc.url, &mut batch)?;
+
+        for (field, token) in &tokens {
+            let posting = postings.entry(token.term.clone()).or_insert_with(|| Posting {
+                doc_id,
+                tf: 0,
+                positions: vec![],
+                field_tf: [0; Field::COUNT]
+            });
+            posting.tf += 1;
+            posting.positions.push(token.position as u32);
+            posting.field_tf[field.index()] += 1;
+            field_lengths[field.index()] += 1;
+        }
+
+        let reader = IndexReader::new(&self.ctx);
+        let previous: Option<StoredDocument> = reader.document(doc_id)?;
+        let previous_terms: HashSet<String> = reader.doc_terms(doc_id)?.into_iter().collect();
+        let previous_link_url: Option<String> = reader.document_source(doc_id)?.and_then(|source| source.link_url);
+
+        let stored = StoredDocument {
+            id: doc_id,
+            url: doc.url.clone(),
+            title: doc.title.clone(),
+            length: tokens.len() as u32,
+            field_lengths
+        };
+        let terms: Vec<&String> = postings.keys().collect();
+
+        let mut totals: CollectionTotals = reader.collection_totals()?;
+        if let Some(previous) = &previous {
+            totals.remove(previous);
+        }
+        totals.add(&stored);
+
+        for term in previous_terms.iter().filter(|term| !postings.contains_key(*term)) {
+            batch.delete(keys::posting_key(term, doc_id));
+        }
+        for (term, posting) in &postings {
+            batch.put(keys::posting_key(term, doc_id), serde_json::to_vec(posting)?);
+        }
+        batch.put(keys::doc_key(doc_id), serde_json::to_vec(&stored)?);
+        batch.put(keys::doc_terms_key(doc_id), serde_json::to_vec(&terms)?);
+        batch.put(keys::text_key(doc_id), doc.text.as_bytes());
+        batch.put(keys::source_key(doc_id), serde_json::to_vec(&DocumentSource::of(doc))?);
+        batch.put(keys::url_key(&doc.url), doc_id.to_string());
+        if let Some(previous_link_url) = previous_link_url.filter(|url| Some(url) != doc.link_url.as_ref()) {
+            batch.delete(keys::link_key(&previous_link_url));
+        }
+        if let Some(link_url) = &doc.link_url {
+            batch.put(keys::link_key(link_url), doc_id.to_string());
+        }
+        batch.put(keys::COLLECTION_TOTALS_KEY, serde_json::to_vec(&totals)?);
+
+        self.ctx.rocks_con.write(batch)?;
+        Ok(doc_id)
     }
-}
\ No newline at end of file
+
+    /// Drops `url` and its postings from the index. Returns whether it
+    /// was indexed. Anchor text the page links to others with is kept.
+    pub fn remove(&self, url: &str) -> Result<bool, IndexerError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let reader = IndexReader::new(&self.ctx);
+        let Some(doc_id) = reader.doc_id(url)? else {
+            return Ok(false);
+        };
+        let previous: Option<StoredDocument> = reader.document(doc_id)?;
+        let terms: Vec<String> = reader.doc_terms(doc_id)?;
+        let link_url: Option<String> = reader.document_source(doc_id)?.and_then(|source| source.link_url);
+
+        let mut batch = rocksdb::WriteBatch::default();
+        if let Some(previous) = &previous {
+            let mut totals: CollectionTotals = reader.collection_totals()?;
+            totals.remove(previous);
+            batch.put(keys::COLLECTION_TOTALS_KEY, serde_json::to_vec(&totals)?);
+        }
+        for term in &terms {
+            batch.delete(keys::posting_key(term, doc_id));
+        }
+        batch.delete(keys::doc_key(doc_id));
+        batch.delete(keys::doc_terms_key(doc_id));
+        batch.delete(keys::text_key(doc_id));
+        batch.delete(keys::source_key(doc_id));
+        batch.delete(keys::pagerank_key(doc_id));
+        batch.delete(keys::url_key(url));
+        if let Some(link_url) = link_url {
+            batch.delete(keys::link_key(&link_url));
+        }
+        self.ctx.rocks_con.write(batch)?;
+        Ok(true)
+    }
+
+    /// Replaces the anchor texts `source` links to other pages with by
+    /// `anchors` (target URL, text). Targets whose anchors changed are
+    /// marked for `reindex_anchors`; returns how many were.
+    pub fn set_anchors(&self, source: &str, anchors: &[(String, String)]) -> Result<usize, IndexerError> {
+        let mut by_target: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (target, text) in anchors {
+            let text: &str = text.trim();
+            if target != source && !text.is_empty() {
+                by_target.entry(target.as_str()).or_default().push(text);
+            }
+        }
+
+        let _guard = self.write_lock.lock().unwrap();
+        let db: &rocksdb::DB = &self.ctx.rocks_con;
+        let previous: Vec<String> = match db.get(keys::anchor_targets_key(source))? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => vec![]
+        };
+
+        let mut changed: usize = 0;
+        let mut batch = rocksdb::WriteBatch::default();
+        for target in previous.iter().filter(|target| !by_target.contains_key(target.as_str())) {
+            batch.delete(keys::anchor_key(target, source));
+            batch.put(keys::stale_anchors_key(target), b"");
+            changed += 1;
+        }
+        for (target, texts) in &by_target {
+            let value: Vec<u8> = serde_json::to_vec(texts)?;
+            if db.get(keys::anchor_key(target, source))?.as_deref() != Some(value.as_slice()) {
+                batch.put(keys::anchor_key(target, source), value);
+                batch.put(keys::stale_anchors_key(target), b"");
+                changed += 1;
+            }
+        }
+        batch.put(keys::anchor_targets_key(source), serde_json::to_vec(&by_target.keys().collect::<Vec<_>>())?);
+        db.write(batch)?;
+        Ok(changed)
+    }
+
+    /// Indexes the targets `set_anchors` marked again, once each, with
+    /// the anchors they have now. Returns how many were indexed; marked
+    /// targets that aren't pick their anchors up when they are.
+    pub fn reindex_anchors(&self) -> Result<usize, IndexerError> {
+        let prefix: &[u8] = keys::STALE_ANCHORS_PREFIX.as_bytes();
+        let mut targets: Vec<String> = vec![];
+
+        let iter = self.ctx.rocks_con.iterator(rocksdb::IteratorMode::From(prefix, rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, _) = key_value?;
+            let Some(target) = key.strip_prefix(prefix) else {
+                break;
+            };
+            targets.push(String::from_utf8_lossy(target).into_owned());
+        }
+
+        let mut reindexed: usize = 0;
+        for target in targets {
+            // unmarked first, so anchors set while indexing mark it again
+            self.ctx.rocks_con.delete(keys::stale_anchors_key(&target))?;
+
+            let reader = IndexReader::new(&self.ctx);
+            let Some(doc_id) = reader.linked_doc_id(&target)? else {
+                continue;
+            };
+            if let Some(doc) = reader.source_document(doc_id)? {
+                self.create_index(&doc)?;
+                reindexed += 1;
+            }
+        }
+        Ok(reindexed)
+    }
+
+    /// Replaces the PageRank of the given documents, see `IndexReader::pagerank`.
+    pub fn set_pageranks(&self, ranks: &[(u64, f32)]) -> Result<(), IndexerError> {
+        let mut batch = rocksdb::WriteBatch::default();
+        for (doc_id, rank) in ranks {
+            batch.put(keys::pagerank_key(*doc_id), rank.to_string());
+        }
+        self.ctx.rocks_con.write(batch)?;
+        Ok(())
+    }
+
+    /// Tokens of every field, in `Field::ALL` order. Positions keep
+    /// counting across fields with a gap of `FIELD_POSITION_GAP`.
+    fn tokenize_fields(doc: &Document) -> Vec<(Field, wp::Token)> {
+        let mut tokens: Vec<(Field, wp::Token)> = vec![];
+        let mut offset: usize = 0;
+
+        for field in Field::ALL {
+            for value in doc.field_values(field) {
+                let value_tokens: Vec<wp::Token> = wp::tokenize(value);
+                let next_offset: usize = match value_tokens.last() {
+                    Some(token) => offset + token.position + 1,
+                    None => continue
+                };
+
+                for mut token in value_tokens {
+                    token.position += offset;
+                    tokens.push((field, token));
+                }
+                offset = next_offset + FIELD_POSITION_GAP;
+            }
+        }
+        tokens
+    }
+
+    /// Returns the id already assigned to `url`, or allocates a new one
+    /// in `batch`, so it's only taken when the document is written.
+    fn doc_id_for(&self, url: &str, batch: &mut rocksdb::WriteBatch) -> Result<u64, IndexerError> {
+        if let Some(doc_id) = IndexReader::new(&self.ctx).doc_id(url)? {
+            return Ok(doc_id);
+        }
+
+        let next_id: u64 = self.ctx.rocks_con.get(keys::NEXT_DOC_ID_KEY)?
+            .and_then(|value| String::from_utf8_lossy(&value).parse::<u64>().ok())
+            .unwrap_or(0);
+
+        batch.put(keys::NEXT_DOC_ID_KEY, (next_id + 1).to_string());
+        batch.put(keys::url_key(url), next_id.to_string());
+        Ok(next_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testkit::TestCtx;
+
+    use super::*;
+
+    fn sample_doc(url: &str) -> Document {
+        Document {
+            url: url.to_string(),
+            title: "Compound statement".to_string(),
+            text: "The for statement is used to iterate over the elements of a sequence.".to_string(),
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn test_create_index_writes_postings() {
+        let (_ctx, indexer) = TestCtx::with("indexer-postings", Indexer::new);
+        let doc_id = indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+
+        let reader = IndexReader::new(indexer.ctx());
+        let postings = reader.postings("statement").unwrap();
+        assert_eq!(postings.len(), 1);
+        assert_eq!(postings[0].doc_id, doc_id);
+        assert_eq!(postings[0].tf, 2);
+        assert_eq!(postings[0].positions, vec![1, 104]);
+        assert_eq!(postings[0].field_tf[Field::Title.index()], 1);
+        assert_eq!(postings[0].field_tf[Field::Body.index()], 1);
+
+        let stored = reader.document(doc_id).unwrap().unwrap();
+        assert_eq!(stored.url, "https://example.com/a");
+        assert_eq!(stored.title, "Compound statement");
+        assert_eq!(stored.field_lengths[Field::Title.index()], 2);
+
+        let text = reader.document_text(doc_id).unwrap().unwrap();
+        assert!(text.starts_with("The for statement"));
+    }
+
+    #[test]
+    fn test_fields_are_indexed_separately() {
+        let (_ctx, indexer) = TestCtx::with("indexer-fields", Indexer::new);
+        let doc = Document {
+            headings: vec!["Iteration".to_string(), "Sequence protocols".to_string()],
+            description: "How python iterates".to_string(),
+            anchor_text: vec!["for loops".to_string()],
+            ..sample_doc("https://example.com/a")
+        };
+        indexer.create_index(&doc).unwrap();
+
+        let reader = IndexReader::new(indexer.ctx());
+        let sequence = &reader.postings("sequence").unwrap()[0];
+        assert_eq!(sequence.field_tf[Field::Headings.index()], 1);
+        assert_eq!(sequence.field_tf[Field::Body.index()], 1);
+
+        let python = &reader.postings("python").unwrap()[0];
+        assert_eq!(python.field_tf[Field::Description.index()], 1);
+        assert_eq!(python.tf, 1);
+
+        let loops = &reader.postings("loops").unwrap()[0];
+        assert_eq!(loops.field_tf[Field::Anchor.index()], 1);
+    }
+
+    #[test]
+    fn test_doc_ids_are_stable_per_url() {
+        let (_ctx, indexer) = TestCtx::with("indexer-doc-ids", Indexer::new);
+        let first = indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+        let second = indexer.create_index(&sample_doc("https://example.com/b")).unwrap();
+        let again = indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(first, again);
+        assert_eq!(IndexReader::new(indexer.ctx()).postings("sequence").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_reindex_replaces_stale_postings() {
+        let (_ctx, indexer) = TestCtx::with("indexer-reindex", Indexer::new);
+        let doc_id = indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+
+        let updated = Document {
+            text: "The while statement is used for repeated execution.".to_string(),
+            ..sample_doc("https://example.com/a")
+        };
+        assert_eq!(indexer.create_index(&updated).unwrap(), doc_id);
+
+        let reader = IndexReader::new(indexer.ctx());
+        assert!(reader.postings("sequence").unwrap().is_empty());
+        assert_eq!(reader.postings("execution").unwrap().len(), 1);
+        assert_eq!(reader.postings("statement").unwrap()[0].tf, 2);
+    }
+
+    #[test]
+    fn test_collection_totals_follow_the_index() {
+        let (_ctx, indexer) = TestCtx::with("indexer-totals", Indexer::new);
+        indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+        indexer.create_index(&sample_doc("https://example.com/b")).unwrap();
+        indexer.create_index(&Document { text: "Shorter now.".to_string(), ..sample_doc("https://example.com/b") }).unwrap();
+
+        let reader = IndexReader::new(indexer.ctx());
+        let totals = reader.collection_totals().unwrap();
+        assert_eq!(totals.docs, 2);
+        assert_eq!(totals.field_lengths[Field::Title.index()], 4);
+        assert_eq!(totals.length, totals.field_lengths.iter().sum::<u64>());
+
+        indexer.remove("https://example.com/a").unwrap();
+        let totals = reader.collection_totals().unwrap();
+        assert_eq!(totals.docs, 1);
+        assert_eq!(totals.length, reader.document(1).unwrap().unwrap().length as u64);
+    }
+
+    #[test]
+    fn test_anchor_text_is_indexed_for_the_target() {
+        let (_ctx, indexer) = TestCtx::with("indexer-anchors", Indexer::new);
+        let target = indexer.create_index(&sample_doc("https://example.com/target")).unwrap();
+        let changed = indexer.set_anchors("https://example.com/a", &[
+            ("https://example.com/target".to_string(), "Python loops guide".to_string()),
+            ("https://example.com/later".to_string(), "Tutorial".to_string()),
+            ("https://example.com/a".to_string(), "Self".to_string())
+        ]).unwrap();
+        assert_eq!(changed, 2);
+
+        // targets are only indexed again in a batch, once each
+        let reader = IndexReader::new(indexer.ctx());
+        assert!(reader.postings("guide").unwrap().is_empty());
+        assert_eq!(indexer.reindex_anchors().unwrap(), 1);
+        assert_eq!(indexer.reindex_anchors().unwrap(), 0);
+        assert_eq!(reader.postings("statement").unwrap()[0].tf, 2, "the rest of the document is indexed as before");
+
+        let guide = reader.postings("guide").unwrap();
+        assert_eq!(guide.len(), 1);
+        assert_eq!(guide[0].doc_id, target);
+        assert_eq!(guide[0].field_tf[Field::Anchor.index()], 1);
+        assert!(reader.postings("self").unwrap().is_empty());
+
+        // anchors recorded before the target is indexed are picked up by it
+        let later = indexer.create_index(&sample_doc("https://example.com/later")).unwrap();
+        assert_eq!(reader.postings("tutorial").unwrap()[0].doc_id, later);
+
+        // the linking page changed and dropped its link
+        indexer.set_anchors("https://example.com/a", &[("https://example.com/later".to_string(), "Tutorial".to_string())]).unwrap();
+        indexer.reindex_anchors().unwrap();
+        assert!(reader.postings("guide").unwrap().is_empty());
+        assert_eq!(reader.document(target).unwrap().unwrap().field_lengths[Field::Anchor.index()], 0);
+    }
+
+    #[test]
+    fn test_anchors_follow_the_link_url() {
+        let (_ctx, indexer) = TestCtx::with("indexer-link-url", Indexer::new);
+        indexer.set_anchors("https://example.com/a", &[("https://example.com/b".to_string(), "Tutorial".to_string())]).unwrap();
+
+        let doc = Document { link_url: Some("https://example.com/b".to_string()), ..sample_doc("https://EXAMPLE.com/b#top") };
+        let doc_id = indexer.create_index(&doc).unwrap();
+        let reader = IndexReader::new(indexer.ctx());
+        assert_eq!(reader.postings("tutorial").unwrap()[0].doc_id, doc_id);
+        assert_eq!(reader.linked_doc_id("https://example.com/b").unwrap(), Some(doc_id));
+        assert_eq!(reader.linked_doc_id("https://EXAMPLE.com/b#top").unwrap(), Some(doc_id));
+
+        // changed anchors re-index the document they lead to
+        assert_eq!(indexer.set_anchors("https://example.com/a", &[]).unwrap(), 1);
+        assert_eq!(indexer.reindex_anchors().unwrap(), 1);
+        assert!(reader.postings("tutorial").unwrap().is_empty());
+
+        indexer.remove("https://EXAMPLE.com/b#top").unwrap();
+        assert_eq!(reader.linked_doc_id("https://example.com/b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_noindex_documents_are_refused_and_removed() {
+        let (_ctx, indexer) = TestCtx::with("indexer-noindex", Indexer::new);
+        let doc_id = indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+        indexer.set_pageranks(&[(doc_id, 2.0)]).unwrap();
+
+        let noindex = Document { noindex: true, ..sample_doc("https://example.com/a") };
+        assert!(matches!(indexer.create_index(&noindex), Err(IndexerError::NoIndex(_))));
+
+        let reader = IndexReader::new(indexer.ctx());
+        assert!(reader.postings("sequence").unwrap().is_empty());
+        assert_eq!(reader.doc_id("https://example.com/a").unwrap(), None);
+        assert_eq!(reader.document(doc_id).unwrap(), None);
+        assert_eq!(reader.pagerank(doc_id).unwrap(), None);
+        assert!(!indexer.remove("https://example.com/a").unwrap());
+    }
+
+    #[test]
+    fn test_pageranks_are_stored_per_document() {
+        let (_ctx, indexer) = TestCtx::with("indexer-pagerank", Indexer::new);
+        let a = indexer.create_index(&sample_doc("https://example.com/a")).unwrap();
+        let b = indexer.create_index(&sample_doc("https://example.com/b")).unwrap();
+        indexer.set_pageranks(&[(a, 1.5)]).unwrap();
+
+        let reader = IndexReader::new(indexer.ctx());
+        assert_eq!(reader.pagerank(a).unwrap(), Some(1.5));
+        assert_eq!(reader.pagerank(b).unwrap(), None);
+    }
+}
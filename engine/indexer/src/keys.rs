@@ -0,0 +1,81 @@
+//! RocksDB key layout of the index.
+//! 
+//! Every key is plain ASCII so tools that walk the keyspace as strings
+//! (`CTX::get_partially_matching_keys`) keep working.
+
+pub(crate) const NEXT_DOC_ID_KEY: &str = "internal:next_doc_id";
+
+/// `CollectionTotals` as JSON.
+pub(crate) const COLLECTION_TOTALS_KEY: &str = "internal:collection_totals";
+
+/// One key per (term, document) pair, so appending a document never
+/// rewrites an existing posting list. Doc ids are zero padded to keep
+/// a term's postings sorted by id.
+pub(crate) fn posting_key(term: &str, doc_id: u64) -> String {
+    format!("posting:{}:{:020}", term, doc_id)
+}
+
+pub(crate) fn posting_prefix(term: &str) -> String {
+    format!("posting:{}:", term)
+}
+
+pub(crate) const DOC_PREFIX: &str = "doc:";
+
+pub(crate) fn doc_key(doc_id: u64) -> String {
+    format!("doc:{:020}", doc_id)
+}
+
+/// Body text of a document, kept apart from `doc:` so scoring doesn't
+/// have to deserialize it.
+pub(crate) fn text_key(doc_id: u64) -> String {
+    format!("text:{:020}", doc_id)
+}
+
+pub(crate) fn url_key(url: &str) -> String {
+    format!("url:{}", url)
+}
+
+/// Document a `Document::link_url` leads to.
+pub(crate) fn link_key(url: &str) -> String {
+    format!("link:{}", url)
+}
+
+/// Terms a document was last indexed with, so a re-index can drop
+/// postings the new version no longer has.
+pub(crate) fn doc_terms_key(doc_id: u64) -> String {
+    format!("terms:{:020}", doc_id)
+}
+
+/// PageRank of a document, written by the crawler's link analysis.
+pub(crate) fn pagerank_key(doc_id: u64) -> String {
+    format!("pagerank:{:020}", doc_id)
+}
+
+/// Fields of the document as given to the indexer that aren't kept
+/// under another key, so it can be indexed again when anchors change.
+pub(crate) fn source_key(doc_id: u64) -> String {
+    format!("source:{:020}", doc_id)
+}
+
+/// Anchor texts of the links from `source` to `target`, one key per
+/// linking page. Normalized URLs never contain a space, so a target's
+/// anchors share the prefix.
+pub(crate) fn anchor_key(target: &str, source: &str) -> String {
+    format!("anchor:{} {}", target, source)
+}
+
+pub(crate) fn anchor_prefix(target: &str) -> String {
+    format!("anchor:{} ", target)
+}
+
+/// Targets `source` was last indexed linking to with anchor text.
+pub(crate) fn anchor_targets_key(source: &str) -> String {
+    format!("anchor-targets:{}", source)
+}
+
+/// Empty; marks a target whose anchors changed since it was indexed.
+pub(crate) fn stale_anchors_key(target: &str) -> String {
+    format!("{}{}", STALE_ANCHORS_PREFIX, target)
+}
+
+pub(crate) const STALE_ANCHORS_PREFIX: &str = "stale-anchors:";
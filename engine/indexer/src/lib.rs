@@ -1,3 +1,9 @@
 mod indexer;
+mod keys;
+mod posting;
+mod reader;
+pub mod errors;
 
-pub use indexer::*;
\ No newline at end of file
+pub use indexer::*;
+pub use posting::*;
+pub use reader::*;
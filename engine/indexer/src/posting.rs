@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+/// The parts of a page that are indexed and scored separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Field {
+    Title,
+    Headings,
+    Description,
+    Body,
+
+    /// Text of links on other pages that point to this one.
+    Anchor
+}
+
+impl Field {
+    pub const COUNT: usize = 5;
+
+    pub const ALL: [Field; Field::COUNT] = [
+        Field::Title,
+        Field::Headings,
+        Field::Description,
+        Field::Body,
+        Field::Anchor
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Headings => "headings",
+            Field::Description => "description",
+            Field::Body => "body",
+            Field::Anchor => "anchor"
+        }
+    }
+}
+
+/// One counter per field, indexed by `Field::index`.
+pub type FieldCounts = [u32; Field::COUNT];
+
+/// Input to the indexer; the crawler builds one from each fetched page.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub url: String,
+    pub title: String,
+    pub text: String,
+    pub headings: Vec<String>,
+
+    /// Content of `<meta name="description">`.
+    pub description: String,
+
+    /// Anchor text of links pointing to this document. The indexer adds
+    /// the anchors recorded with `Indexer::set_anchors`.
+    pub anchor_text: Vec<String>,
+
+    /// URL links to this document are recorded under, when it isn't
+    /// `url` itself; the crawler passes `url` normalized.
+    #[serde(default)]
+    pub link_url: Option<String>,
+
+    /// The page carries a `noindex` robots directive. The indexer refuses
+    /// such documents.
+    #[serde(default)]
+    pub noindex: bool
+}
+
+impl Document {
+    /// Text values of `field`; multi-valued fields yield one entry per value.
+    pub fn field_values(&self, field: Field) -> Vec<&str> {
+        match field {
+            Field::Title => vec![self.title.as_str()],
+            Field::Headings => self.headings.iter().map(String::as_str).collect(),
+            Field::Description => vec![self.description.as_str()],
+            Field::Body => vec![self.text.as_str()],
+            Field::Anchor => self.anchor_text.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+/// One entry of a term's posting list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Posting {
+    pub doc_id: u64,
+
+    /// Number of times the term occurs in the document.
+    pub tf: u32,
+
+    /// Word positions of every occurrence, in ascending order.
+    pub positions: Vec<u32>,
+
+    /// `tf` split up by field.
+    #[serde(default)]
+    pub field_tf: FieldCounts
+}
+
+impl Posting {
+    /// Positions of the occurrences in `field`. Fields are tokenized one
+    /// after the other in `Field::ALL` order, so each one is a run of
+    /// `positions` as long as its `field_tf`.
+    pub fn field_positions(&self, field: Field) -> &[u32] {
+        let start: usize = self.field_tf[..field.index()].iter().map(|tf| *tf as usize).sum();
+        let end: usize = start + self.field_tf[field.index()] as usize;
+        self.positions.get(start..end).unwrap_or_default()
+    }
+}
+
+/// Fields of a `Document` kept nowhere else in the index; with its
+/// `StoredDocument` and text they make up the whole document.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DocumentSource {
+    pub headings: Vec<String>,
+    pub description: String,
+    pub anchor_text: Vec<String>,
+    pub link_url: Option<String>
+}
+
+impl DocumentSource {
+    pub fn of(doc: &Document) -> Self {
+        Self {
+            headings: doc.headings.clone(),
+            description: doc.description.clone(),
+            anchor_text: doc.anchor_text.clone(),
+            link_url: doc.link_url.clone()
+        }
+    }
+}
+
+/// What the index remembers about a document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredDocument {
+    pub id: u64,
+    pub url: String,
+    pub title: String,
+
+    /// Number of indexed terms.
+    pub length: u32,
+
+    /// `length` split up by field.
+    #[serde(default)]
+    pub field_lengths: FieldCounts
+}
+
+/// Totals over every indexed document, updated in the same write as the
+/// documents themselves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionTotals {
+    pub docs: u64,
+
+    /// Summed `StoredDocument::length`.
+    pub length: u64,
+
+    /// Summed `StoredDocument::field_lengths`.
+    pub field_lengths: [u64; Field::COUNT]
+}
+
+impl CollectionTotals {
+    pub(crate) fn add(&mut self, doc: &StoredDocument) {
+        self.docs += 1;
+        self.length += doc.length as u64;
+        for field in Field::ALL {
+            self.field_lengths[field.index()] += doc.field_lengths[field.index()] as u64;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, doc: &StoredDocument) {
+        self.docs = self.docs.saturating_sub(1);
+        self.length = self.length.saturating_sub(doc.length as u64);
+        for field in Field::ALL {
+            let total: &mut u64 = &mut self.field_lengths[field.index()];
+            *total = total.saturating_sub(doc.field_lengths[field.index()] as u64);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use context::CTX;
+
+use crate::{
+    errors::IndexerError, 
+    keys, 
+    CollectionTotals, 
+    Document, 
+    DocumentSource, 
+    Posting, 
+    StoredDocument
+};
+
+/// Read-only view over the index stored in `CTX`.
+pub struct IndexReader<'a> {
+    db: &'a rocksdb::DB
+}
+
+impl<'a> IndexReader<'a> {
+    pub fn new(ctx: &'a CTX) -> Self {
+        Self { db: &ctx.rocks_con }
+    }
+
+    /// Returns the posting list of `term`, ordered by doc id.
+    pub fn postings(&self, term: &str) -> Result<Vec<Posting>, IndexerError> {
+        let prefix: String = keys::posting_prefix(term);
+        let mut postings: Vec<Posting> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, value) = key_value?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            postings.push(serde_json::from_slice(&value)?);
+        }
+        Ok(postings)
+    }
+
+    pub fn document(&self, doc_id: u64) -> Result<Option<StoredDocument>, IndexerError> {
+        match self.db.get(keys::doc_key(doc_id))? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Body text the document was last indexed with, for snippets.
+    pub fn document_text(&self, doc_id: u64) -> Result<Option<String>, IndexerError> {
+        let text = self.db.get(keys::text_key(doc_id))?
+            .map(|value| String::from_utf8_lossy(&value).into_owned());
+        Ok(text)
+    }
+
+    /// Document count and lengths summed over the whole index.
+    pub fn collection_totals(&self) -> Result<CollectionTotals, IndexerError> {
+        match self.db.get(keys::COLLECTION_TOTALS_KEY)? {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Ok(CollectionTotals::default())
+        }
+    }
+
+    /// Ids of every indexed document.
+    pub fn doc_ids(&self) -> Result<Vec<u64>, IndexerError> {
+        let mut ids: Vec<u64> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(keys::DOC_PREFIX.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, _) = key_value?;
+            let Some(id) = key.strip_prefix(keys::DOC_PREFIX.as_bytes()) else {
+                break;
+            };
+            if let Ok(id) = String::from_utf8_lossy(id).parse::<u64>() {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Distinct terms the document was last indexed with.
+    pub fn doc_terms(&self, doc_id: u64) -> Result<Vec<String>, IndexerError> {
+        match self.db.get(keys::doc_terms_key(doc_id))? {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Ok(vec![])
+        }
+    }
+
+    /// Anchor texts other pages link to `url` with, by linking page.
+    pub fn anchor_texts(&self, url: &str) -> Result<Vec<String>, IndexerError> {
+        let prefix: String = keys::anchor_prefix(url);
+        let mut anchors: Vec<String> = vec![];
+
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward));
+        for key_value in iter {
+            let (key, value) = key_value?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            anchors.extend(serde_json::from_slice::<Vec<String>>(&value)?);
+        }
+        Ok(anchors)
+    }
+
+    pub(crate) fn document_source(&self, doc_id: u64) -> Result<Option<DocumentSource>, IndexerError> {
+        match self.db.get(keys::source_key(doc_id))? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None)
+        }
+    }
+
+    /// The document as last given to the indexer.
+    pub(crate) fn source_document(&self, doc_id: u64) -> Result<Option<Document>, IndexerError> {
+        let (Some(stored), Some(source)) = (self.document(doc_id)?, self.document_source(doc_id)?) else {
+            return Ok(None);
+        };
+        Ok(Some(Document {
+            url: stored.url,
+            title: stored.title,
+            text: self.document_text(doc_id)?.unwrap_or_default(),
+            headings: source.headings,
+            description: source.description,
+            anchor_text: source.anchor_text,
+            link_url: source.link_url,
+            noindex: false
+        }))
+    }
+
+    /// PageRank of the document, scaled so the average page has 1.0;
+    /// `None` until the link analysis has seen it.
+    pub fn pagerank(&self, doc_id: u64) -> Result<Option<f32>, IndexerError> {
+        let rank = self.db.get(keys::pagerank_key(doc_id))?
+            .and_then(|value| String::from_utf8_lossy(&value).parse::<f32>().ok());
+        Ok(rank)
+    }
+
+    /// Id of the document links to `url` lead to: the one whose
+    /// `Document::link_url` it is, or else the one indexed under it.
+    pub fn linked_doc_id(&self, url: &str) -> Result<Option<u64>, IndexerError> {
+        match self.db.get(keys::link_key(url))? {
+            Some(value) => Ok(String::from_utf8_lossy(&value).parse::<u64>().ok()),
+            None => self.doc_id(url)
+        }
+    }
+
+    pub fn doc_id(&self, url: &str) -> Result<Option<u64>, IndexerError> {
+        let id = self.db.get(keys::url_key(url))?
+            .and_then(|value| String::from_utf8_lossy(&value).parse::<u64>().ok());
+        Ok(id)
+    }
+}
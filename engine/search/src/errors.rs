@@ -0,0 +1,35 @@
+use std::fmt;
+
+use indexer::errors::IndexerError;
+
+use crate::ParseError;
+
+#[derive(Debug)]
+pub enum SearchError {
+    /// The query string isn't valid query language.
+    Parse(ParseError),
+    Index(IndexerError)
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Parse(err) => write!(f, "invalid query: {}", err),
+            SearchError::Index(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<ParseError> for SearchError {
+    fn from(err: ParseError) -> Self {
+        SearchError::Parse(err)
+    }
+}
+
+impl From<IndexerError> for SearchError {
+    fn from(err: IndexerError) -> Self {
+        SearchError::Index(err)
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use context::CTX;
+use indexer::{errors::IndexerError, Field, FieldCounts, IndexReader, Posting};
+use wp::best_ngram_match;
+
+use crate::{near_matches, phrase_matches, Query, ScoringPostings, TermPostings};
+
+/// Runs a parsed `Query` against the index.
+pub(crate) struct Evaluator<'a> {
+    ctx: &'a CTX,
+    reader: &'a IndexReader<'a>,
+    postings: TermPostings,
+
+    /// Every indexed document; only loaded when a query needs it (`NOT`, `site:`...).
+    universe: Option<HashSet<u64>>,
+    urls: HashMap<u64, Option<String>>
+}
+
+impl<'a> Evaluator<'a> {
+    pub(crate) fn new(ctx: &'a CTX, reader: &'a IndexReader<'a>) -> Self {
+        Self {
+            ctx,
+            reader,
+            postings: HashMap::new(),
+            universe: None,
+            urls: HashMap::new()
+        }
+    }
+
+    /// Fetches the postings of every term in `query`. Plain terms outside
+    /// a `NOT` fall back to the closest indexed term when they're unknown.
+    pub(crate) fn load(&mut self, query: &Query) -> Result<(), IndexerError> {
+        let mut terms: Vec<QueryTerm> = vec![];
+        collect_terms(query, false, &mut terms);
+
+        for QueryTerm { term, fuzzy, .. } in terms {
+            if !self.postings.contains_key(&term) {
+                let list: Vec<Posting> = fetch_postings(self.ctx, self.reader, &term, fuzzy)?;
+                self.postings.insert(term, list.into_iter().map(|posting| (posting.doc_id, posting)).collect());
+            }
+        }
+        Ok(())
+    }
+
+    /// Postings of the terms that count towards a document's score,
+    /// i.e. everything that isn't negated. A term restricted to a field
+    /// only keeps its occurrences in that field.
+    pub(crate) fn scoring_postings(&self, query: &Query) -> ScoringPostings {
+        let mut terms: Vec<QueryTerm> = vec![];
+        collect_terms(query, false, &mut terms);
+
+        terms.into_iter()
+            .filter(|term| !term.negated)
+            .filter_map(|QueryTerm { term, field, .. }| {
+                let docs: HashMap<u64, Posting> = self.postings.get(&term)?.iter()
+                    .filter_map(|(doc_id, posting)| Some((*doc_id, in_field_only(posting, field)?)))
+                    .collect();
+                Some(((term, field), docs))
+            })
+            .collect()
+    }
+
+    /// Documents matching `query`.
+    pub(crate) fn eval(&mut self, query: &Query) -> Result<HashSet<u64>, IndexerError> {
+        match query {
+            Query::Term { term, field } => Ok(self.docs_of(term)
+                .map(|docs| {
+                    docs.values()
+                        .filter(|posting| in_field(posting, *field))
+                        .map(|posting| posting.doc_id)
+                        .collect()
+                })
+                .unwrap_or_default()),
+            Query::Phrase { terms, field } => Ok(self.phrase_docs(terms, *field)),
+            Query::Near { left, right, distance } => {
+                let (Some(left_docs), Some(right_docs)) = (self.docs_of(left), self.docs_of(right)) else {
+                    return Ok(HashSet::new());
+                };
+
+                Ok(left_docs.iter()
+                    .filter(|(doc_id, left)| {
+                        right_docs.get(doc_id).is_some_and(|right| near_matches(&left.positions, &right.positions, *distance))
+                    })
+                    .map(|(doc_id, _)| *doc_id)
+                    .collect())
+            },
+            Query::Site(_) | Query::Url(_) => {
+                let mut docs: HashSet<u64> = self.universe()?.clone();
+                self.retain_matching(&mut docs, std::slice::from_ref(query))?;
+                Ok(docs)
+            },
+            Query::Not(inner) => {
+                let excluded: HashSet<u64> = self.eval(inner)?;
+                Ok(self.universe()?.difference(&excluded).copied().collect())
+            },
+            Query::Or(children) => {
+                let mut docs: HashSet<u64> = HashSet::new();
+                for child in children {
+                    docs.extend(self.eval(child)?);
+                }
+                Ok(docs)
+            },
+            Query::And(children) => {
+                let (filters, rest): (Vec<Query>, Vec<Query>) = children.iter()
+                    .cloned()
+                    .partition(|child| matches!(child, Query::Site(_) | Query::Url(_)));
+                let (negated, positive): (Vec<Query>, Vec<Query>) = rest.into_iter()
+                    .partition(|child| matches!(child, Query::Not(_)));
+
+                let mut docs: HashSet<u64> = match positive.split_first() {
+                    Some((first, others)) => {
+                        let mut docs: HashSet<u64> = self.eval(first)?;
+                        for other in others {
+                            let matches: HashSet<u64> = self.eval(other)?;
+                            docs.retain(|doc_id| matches.contains(doc_id));
+                        }
+                        docs
+                    },
+                    None => self.universe()?.clone()
+                };
+
+                self.retain_matching(&mut docs, &filters)?;
+
+                for query in negated {
+                    if let Query::Not(inner) = query {
+                        let excluded: HashSet<u64> = self.eval(&inner)?;
+                        docs.retain(|doc_id| !excluded.contains(doc_id));
+                    }
+                }
+                Ok(docs)
+            }
+        }
+    }
+
+    fn docs_of(&self, term: &str) -> Option<&HashMap<u64, Posting>> {
+        self.postings.get(term)
+    }
+
+    fn phrase_docs(&self, terms: &[(String, u32)], field: Option<Field>) -> HashSet<u64> {
+        let lists: Option<Vec<&HashMap<u64, Posting>>> = terms.iter().map(|(term, _)| self.docs_of(term)).collect();
+        let Some(lists) = lists else {
+            return HashSet::new();
+        };
+
+        lists[0].keys()
+            .filter(|doc_id| {
+                let postings: Option<Vec<&Posting>> = lists.iter().map(|docs| docs.get(doc_id)).collect();
+                postings.is_some_and(|postings| {
+                    // restricted to a field, the whole phrase has to be inside it
+                    let positions: Vec<(&[u32], u32)> = postings.iter()
+                        .zip(terms)
+                        .map(|(posting, (_, offset))| {
+                            let positions: &[u32] = match field {
+                                Some(field) => posting.field_positions(field),
+                                None => &posting.positions
+                            };
+                            (positions, *offset)
+                        })
+                        .collect();
+
+                    phrase_matches(&positions)
+                })
+            })
+            .copied()
+            .collect()
+    }
+
+    fn universe(&mut self) -> Result<&HashSet<u64>, IndexerError> {
+        if self.universe.is_none() {
+            self.universe = Some(self.reader.doc_ids()?.into_iter().collect());
+        }
+        Ok(self.universe.as_ref().unwrap())
+    }
+
+    /// Keeps the documents whose URL passes every `site:` / `url:` filter.
+    fn retain_matching(&mut self, docs: &mut HashSet<u64>, filters: &[Query]) -> Result<(), IndexerError> {
+        if filters.is_empty() {
+            return Ok(());
+        }
+
+        let mut rejected: Vec<u64> = vec![];
+        for doc_id in docs.iter() {
+            if !self.urls.contains_key(doc_id) {
+                let url: Option<String> = self.reader.document(*doc_id)?.map(|doc| doc.url);
+                self.urls.insert(*doc_id, url);
+            }
+
+            let url: &str = self.urls[doc_id].as_deref().unwrap_or_default();
+            if !filters.iter().all(|filter| url_matches(filter, url)) {
+                rejected.push(*doc_id);
+            }
+        }
+
+        for doc_id in rejected {
+            docs.remove(&doc_id);
+        }
+        Ok(())
+    }
+}
+
+/// A term mentioned somewhere in a query.
+struct QueryTerm {
+    term: String,
+
+    /// Whether an unknown term may be swapped for a similar indexed one.
+    fuzzy: bool,
+
+    /// Whether the term sits below a `NOT`.
+    negated: bool,
+
+    /// Field the query restricts the term to.
+    field: Option<Field>
+}
+
+fn collect_terms(query: &Query, negated: bool, terms: &mut Vec<QueryTerm>) {
+    let mut push = |term: &str, fuzzy: bool, field: Option<Field>| {
+        terms.push(QueryTerm { term: term.to_string(), fuzzy, negated, field })
+    };
+
+    match query {
+        Query::Term { term, field } => push(term, !negated && field.is_none(), *field),
+        Query::Phrase { terms: phrase, field } => phrase.iter().for_each(|(term, _)| push(term, false, *field)),
+        Query::Near { left, right, .. } => {
+            push(left, false, None);
+            push(right, false, None);
+        },
+        Query::Site(_) | Query::Url(_) => {},
+        Query::Not(inner) => collect_terms(inner, true, terms),
+        Query::And(children) | Query::Or(children) => {
+            for child in children {
+                collect_terms(child, negated, terms);
+            }
+        }
+    }
+}
+
+fn in_field(posting: &Posting, field: Option<Field>) -> bool {
+    field.is_none_or(|field| posting.field_tf[field.index()] > 0)
+}
+
+/// `posting` cut down to its occurrences in `field`; `None` when there
+/// are none. Without a field, the whole posting.
+fn in_field_only(posting: &Posting, field: Option<Field>) -> Option<Posting> {
+    let Some(field) = field else {
+        return Some(posting.clone());
+    };
+    if !in_field(posting, Some(field)) {
+        return None;
+    }
+
+    let mut field_tf: FieldCounts = [0; Field::COUNT];
+    field_tf[field.index()] = posting.field_tf[field.index()];
+    Some(Posting {
+        doc_id: posting.doc_id,
+        tf: field_tf[field.index()],
+        positions: posting.field_positions(field).to_vec(),
+        field_tf
+    })
+}
+
+fn host_of(url: &str) -> &str {
+    let without_scheme: &str = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority: &str = without_scheme.split(['/', '?', '#']).next().unwrap_or_default();
+    let host: &str = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    host.split(':').next().unwrap_or_default()
+}
+
+fn url_matches(filter: &Query, url: &str) -> bool {
+    match filter {
+        Query::Site(site) => {
+            let host: String = host_of(url).to_lowercase();
+            host == *site || host.ends_with(&format!(".{}", site))
+        },
+        Query::Url(fragment) => url.to_lowercase().contains(&fragment.to_lowercase()),
+        _ => true
+    }
+}
+
+/// Postings of `term`. With `fuzzy`, falls back to the closest indexed
+/// term by trigram overlap when the term itself was never indexed.
+fn fetch_postings(ctx: &CTX, reader: &IndexReader, term: &str, fuzzy: bool) -> Result<Vec<Posting>, IndexerError> {
+    let postings: Vec<Posting> = reader.postings(term)?;
+    if !postings.is_empty() || !fuzzy {
+        return Ok(postings);
+    }
+
+    let similar_terms: Vec<String> = indexed_terms_like(ctx, term);
+    match best_ngram_match(term, &similar_terms) {
+        Some(a_match) => reader.postings(&a_match),
+        None => Ok(vec![])
+    }
+}
+
+/// Indexed terms that contain `fragment`.
+fn indexed_terms_like(ctx: &CTX, fragment: &str) -> Vec<String> {
+    let similar_keys: Vec<String> = CTX::get_partially_matching_keys(&ctx.rocks_con, "posting:", fragment);
+
+    similar_keys.iter()
+        .filter_map(|key| key.strip_prefix("posting:"))
+        .filter_map(|rest| rest.split(':').next())
+        .filter(|term| term.contains(fragment))
+        .map(str::to_string)
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("https://docs.python.org/3/index.html"), "docs.python.org");
+        assert_eq!(host_of("http://user@example.com:8080?q=1"), "example.com");
+    }
+
+    #[test]
+    fn test_site_filter_matches_subdomains() {
+        let site = Query::Site("python.org".to_string());
+        assert!(url_matches(&site, "https://docs.python.org/3/"));
+        assert!(url_matches(&site, "https://python.org/"));
+        assert!(!url_matches(&site, "https://notpython.org/"));
+    }
+}
@@ -1,5 +1,16 @@
 mod query_parser;
 mod query;
+mod ranking;
+mod proximity;
+mod snippet;
+mod page;
+mod eval;
+
+pub mod errors;
 
 pub use query_parser::*;
-pub use query::*;
\ No newline at end of file
+pub use query::*;
+pub use ranking::*;
+pub use proximity::*;
+pub use snippet::*;
+pub use page::*;
@@ -0,0 +1,70 @@
+use context::CTX;
+use indexer::IndexReader;
+
+use crate::{
+    errors::SearchError,
+    parse_query,
+    run_query,
+    snippet,
+    SearchHit,
+    SearchOptions,
+    SNIPPET_WORDS
+};
+
+/// One search result as shown to a user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub url: String,
+    pub title: String,
+    pub snippet: String,
+    pub score: f32
+}
+
+/// One page of ranked results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+
+    /// Number of matching documents over all pages.
+    pub total_hits: usize,
+
+    /// 1-based.
+    pub page: usize,
+    pub per_page: usize
+}
+
+/// Parses `query`, ranks it with `run_query` and cuts page `page`
+/// (1-based) of `per_page` results out of the ranking. Snippets are only
+/// built for the returned page.
+pub fn search_page(
+    ctx: &CTX,
+    query: &str,
+    options: &SearchOptions,
+    page: usize,
+    per_page: usize
+) -> Result<SearchPage, SearchError> {
+    let page: usize = page.max(1);
+    let (hits, terms): (Vec<SearchHit>, Vec<String>) = match parse_query(query, options.mode)? {
+        Some(parsed) => (run_query(ctx, &parsed, options)?, parsed.terms()),
+        None => (vec![], vec![])
+    };
+
+    let reader = IndexReader::new(ctx);
+    let mut results: Vec<SearchResult> = vec![];
+    for hit in hits.iter().skip((page - 1).saturating_mul(per_page)).take(per_page) {
+        let text: String = reader.document_text(hit.doc_id)?.unwrap_or_default();
+        results.push(SearchResult {
+            url: hit.url.clone(),
+            title: hit.title.clone(),
+            snippet: snippet(&text, &terms, SNIPPET_WORDS),
+            score: hit.score
+        });
+    }
+
+    Ok(SearchPage {
+        results,
+        total_hits: hits.len(),
+        page,
+        per_page
+    })
+}
@@ -0,0 +1,116 @@
+//! Matching and scoring on the word positions stored in postings.
+//! Every position list is expected to be sorted ascending.
+
+/// Whether the terms occur one after another at the given offsets,
+/// e.g. `[(list_positions, 0), (comprehension_positions, 1)]`.
+/// Offsets come from the query, so skipped stop words keep their gap.
+pub fn phrase_matches(terms: &[(&[u32], u32)]) -> bool {
+    let Some(((first, first_offset), rest)) = terms.split_first() else {
+        return false;
+    };
+
+    first.iter().any(|&start| {
+        let Some(base) = start.checked_sub(*first_offset) else {
+            return false;
+        };
+        rest.iter().all(|(positions, offset)| positions.binary_search(&(base + offset)).is_ok())
+    })
+}
+
+/// Whether some occurrence of `a` lies within `distance` words of `b`.
+pub fn near_matches(a: &[u32], b: &[u32], distance: u32) -> bool {
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() && j < b.len() {
+        if a[i].abs_diff(b[j]) <= distance {
+            return true;
+        }
+        if a[i] < b[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    false
+}
+
+/// Length of the smallest window of words that contains at least one
+/// occurrence of every list, or `None` if a list is empty.
+pub fn min_span(lists: &[&[u32]]) -> Option<u32> {
+    if lists.is_empty() || lists.iter().any(|list| list.is_empty()) {
+        return None;
+    }
+
+    let mut heads: Vec<usize> = vec![0; lists.len()];
+    let mut best: u32 = u32::MAX;
+
+    loop {
+        let mut low: (u32, usize) = (u32::MAX, 0);
+        let mut high: u32 = 0;
+
+        for (list, head) in heads.iter().enumerate() {
+            let position: u32 = lists[list][*head];
+            if position < low.0 {
+                low = (position, list);
+            }
+            high = high.max(position);
+        }
+        best = best.min(high - low.0 + 1);
+
+        heads[low.1] += 1;
+        if heads[low.1] == lists[low.1].len() {
+            return Some(best);
+        }
+    }
+}
+
+/// Score multiplier for a document whose query terms fall within `span`
+/// words of each other. Terms right next to each other get `1 + weight`;
+/// the boost fades as the span grows.
+pub fn proximity_boost(span: u32, term_count: usize, weight: f32) -> f32 {
+    if term_count < 2 {
+        return 1.0;
+    }
+    1.0 + weight * term_count as f32 / span.max(term_count as u32) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phrase_matches_consecutive_positions() {
+        let list: &[u32] = &[3, 40];
+        let comprehension: &[u32] = &[12, 41];
+        assert!(phrase_matches(&[(list, 0), (comprehension, 1)]));
+        assert!(!phrase_matches(&[(comprehension, 0), (list, 1)]));
+    }
+
+    #[test]
+    fn test_phrase_honors_query_offsets() {
+        // "list of lists": the stop word leaves a gap of two
+        let list: &[u32] = &[7];
+        let lists: &[u32] = &[9];
+        assert!(phrase_matches(&[(list, 0), (lists, 2)]));
+        assert!(!phrase_matches(&[(list, 0), (lists, 1)]));
+    }
+
+    #[test]
+    fn test_near_matches_within_distance() {
+        assert!(near_matches(&[1, 50], &[20, 53], 3));
+        assert!(!near_matches(&[1, 50], &[20, 54], 3));
+    }
+
+    #[test]
+    fn test_min_span() {
+        assert_eq!(min_span(&[&[1, 30], &[8, 32], &[31]]), Some(3));
+        assert_eq!(min_span(&[&[1], &[]]), None);
+    }
+
+    #[test]
+    fn test_proximity_boost_fades_with_span() {
+        assert_eq!(proximity_boost(2, 2, 0.5), 1.5);
+        assert!(proximity_boost(20, 2, 0.5) < proximity_boost(4, 2, 0.5));
+        assert_eq!(proximity_boost(5, 1, 0.5), 1.0);
+    }
+}
@@ -1,26 +1,386 @@
+use std::collections::{HashMap, HashSet};
+
 use context::CTX;
 
-use rocksdb::{self};
-use wp::best_ngram_match;
+use indexer::{errors::IndexerError, Field, FieldCounts, IndexReader, Posting};
+
+use crate::{
+    errors::SearchError, 
+    eval::Evaluator, 
+    min_span, 
+    pagerank_boost, 
+    parse_query, 
+    proximity_boost, 
+    CollectionStats, 
+    Query, 
+    Ranking, 
+    TermStats
+};
+
+/// How the postings of several query terms are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryMode {
+    /// Documents must contain every query term.
+    #[default]
+    And,
+
+    /// Documents must contain at least one query term.
+    Or
+}
+
+/// Per-request knobs of `query_rdb`.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub mode: QueryMode,
+    pub ranking: Ranking,
+
+    /// How much a document gains when its query terms sit close together;
+    /// `0.0` turns the proximity boost off.
+    pub proximity_weight: f32,
+
+    /// How much PageRank moves a document's score, see `pagerank_boost`;
+    /// `0.0` ranks on the text alone.
+    pub pagerank_weight: f32
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            mode: QueryMode::default(),
+            ranking: Ranking::default(),
+            proximity_weight: 0.5,
+            pagerank_weight: 0.5
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub doc_id: u64,
+    pub url: String,
+    pub title: String,
+    pub score: f32
+}
 
-pub fn query_rdb(ctx: &CTX, query: &str) -> Result<String, rocksdb::Error> {
-    if let Some(value) = ctx.rocks_con.get(query)? {
-        let response_str = String::from_utf8_lossy(&value);
-        return Ok(response_str.to_string());
+/// Postings of every query term, keyed by doc id for positional checks.
+pub(crate) type TermPostings = HashMap<String, HashMap<u64, Posting>>;
+
+/// Postings a query is scored with, by term and the field the query
+/// restricts the term to.
+pub(crate) type ScoringPostings = HashMap<(String, Option<Field>), HashMap<u64, Posting>>;
+
+/// Parses `query` with the boolean query language (see `parse_query`),
+/// evaluates it against the index and returns the matching documents
+/// ranked by `options.ranking`, best first. `options.mode` decides how
+/// terms without an explicit operator between them are combined.
+pub fn query_rdb(ctx: &CTX, query: &str, options: &SearchOptions) -> Result<Vec<SearchHit>, SearchError> {
+    match parse_query(query, options.mode)? {
+        Some(parsed) => run_query(ctx, &parsed, options),
+        None => Ok(vec![])
+    }
+}
+
+/// `query_rdb` for a query that's already parsed.
+pub fn run_query(ctx: &CTX, parsed: &Query, options: &SearchOptions) -> Result<Vec<SearchHit>, SearchError> {
+    let reader = IndexReader::new(ctx);
+    let mut evaluator = Evaluator::new(ctx, &reader);
+    evaluator.load(parsed)?;
+
+    let matching_docs: HashSet<u64> = evaluator.eval(parsed)?;
+    let postings: ScoringPostings = evaluator.scoring_postings(parsed);
+    Ok(rank_documents(&reader, &matching_docs, &postings, options)?)
+}
+
+/// Scores `matching_docs` with the ranker picked in `options`, boosts
+/// documents whose terms sit close together, mixes in PageRank and sorts
+/// best first. A term restricted to a field is scored as if the field
+/// were the whole document.
+pub(crate) fn rank_documents(
+    reader: &IndexReader, 
+    matching_docs: &HashSet<u64>, 
+    postings: &ScoringPostings, 
+    options: &SearchOptions
+) -> Result<Vec<SearchHit>, IndexerError> {
+    if matching_docs.is_empty() {
+        return Ok(vec![]);
     }
-    else {
-        let similar_keys: Vec<String> = CTX::get_partially_matching_keys(&ctx.rocks_con, query);
-        let best_match: Option<String> = best_ngram_match(query, &similar_keys);
 
-        if let Some(a_match) = best_match {
-            if let Some(db_match) = ctx.rocks_con.get(a_match)? {
-                let response = String::from_utf8_lossy(&db_match);
-                return Ok(response.to_string());
+    let mut doc_lengths: HashMap<u64, (u32, FieldCounts)> = HashMap::new();
+    let mut pageranks: HashMap<u64, f32> = HashMap::new();
+    let mut hits: HashMap<u64, SearchHit> = HashMap::new();
+
+    for doc_id in matching_docs {
+        if let Some(doc) = reader.document(*doc_id)? {
+            doc_lengths.insert(*doc_id, (doc.length, doc.field_lengths));
+            if let Some(rank) = reader.pagerank(*doc_id)? {
+                pageranks.insert(*doc_id, rank);
             }
+            hits.insert(*doc_id, SearchHit {
+                doc_id: *doc_id,
+                url: doc.url,
+                title: doc.title,
+                score: 0.0
+            });
+        }
+    }
+
+    let matched: f32 = doc_lengths.len().max(1) as f32;
+    let mut avg_field_lengths: [f32; Field::COUNT] = [0.0; Field::COUNT];
+    for field in Field::ALL {
+        avg_field_lengths[field.index()] = doc_lengths.values().map(|(_, fields)| fields[field.index()]).sum::<u32>() as f32 / matched;
+    }
+
+    let fallback = CollectionStats {
+        total_docs: postings.values().map(HashMap::len).max().unwrap_or(1),
+        avg_doc_length: doc_lengths.values().map(|(length, _)| *length).sum::<u32>() as f32 / matched,
+        avg_field_lengths
+    };
+    let collection = CollectionStats::from_index(reader, fallback);
+    let ranker = options.ranking.ranker();
+
+    for (doc_id, hit) in hits.iter_mut() {
+        let (doc_length, field_lengths) = doc_lengths[doc_id];
+        let mut positions: Vec<&[u32]> = vec![];
+
+        for ((_, field), docs) in postings {
+            let Some(posting) = docs.get(doc_id) else {
+                continue;
+            };
+
+            let term = TermStats {
+                term_freq: posting.tf,
+                doc_length: field.map_or(doc_length, |field| field_lengths[field.index()]),
+                doc_freq: docs.len(),
+                field_freqs: posting.field_tf,
+                field_lengths
+            };
+            let collection = match field {
+                Some(field) => CollectionStats { avg_doc_length: collection.avg_field_lengths[field.index()], ..collection },
+                None => collection
+            };
+            hit.score += ranker.score(&term, &collection);
+            positions.push(&posting.positions);
+        }
+
+        if let Some(span) = min_span(&positions) {
+            hit.score *= proximity_boost(span, positions.len(), options.proximity_weight);
+        }
+        if let Some(rank) = pageranks.get(doc_id) {
+            hit.score *= pagerank_boost(*rank, options.pagerank_weight);
+        }
+    }
+
+    let mut ranked: Vec<SearchHit> = hits.into_values().collect();
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.doc_id.cmp(&b.doc_id)));
+    Ok(ranked)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use indexer::{Document, Indexer};
+    use testkit::TestCtx;
+
+    use crate::Bm25F;
+
+    use super::*;
+
+    fn indexed_ctx(name: &str) -> TestCtx {
+        let ctx = TestCtx::new(&format!("search-{}", name));
+        index_pages(&ctx, &[
+            ("https://example.com/for", "The for loop", "A for loop in python iterates over a sequence."),
+            ("https://example.com/while", "The while loop", "A while loop in python repeats while a condition holds."),
+            ("https://example.com/lists", "Lists", "Lists are mutable sequences in python.")
+        ]);
+        ctx
+    }
+
+    /// `indexed_ctx` plus a page where term order and distance matter.
+    fn positional_ctx(name: &str) -> TestCtx {
+        let ctx = indexed_ctx(name);
+        index_pages(&ctx, &[
+            ("https://example.com/comprehensions", "Comprehensions", "A list comprehension builds a list. Python generators come later.")
+        ]);
+        ctx
+    }
+
+    fn index_pages(ctx: &Arc<CTX>, pages: &[(&str, &str, &str)]) {
+        let indexer = Indexer::new(ctx.clone());
+        for (url, title, text) in pages {
+            indexer.create_index(&Document {
+                url: url.to_string(),
+                title: title.to_string(),
+                text: text.to_string(),
+                ..Document::default()
+            }).unwrap();
         }
-        else {
-            return Ok(String::new());
+    }
+
+    fn options(mode: QueryMode) -> SearchOptions {
+        SearchOptions { mode, ..SearchOptions::default() }
+    }
+
+    fn urls(hits: &[SearchHit]) -> Vec<&str> {
+        let mut urls: Vec<&str> = hits.iter().map(|hit| hit.url.as_str()).collect();
+        urls.sort();
+        urls
+    }
+
+    #[test]
+    fn test_and_requires_every_term() {
+        let ctx = indexed_ctx("and");
+        let hits = query_rdb(&ctx, "python for loop", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/for", "https://example.com/while"]);
+
+        let hits = query_rdb(&ctx, "loop mutable", &options(QueryMode::And)).unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_or_accepts_any_term() {
+        let ctx = indexed_ctx("or");
+        let hits = query_rdb(&ctx, "loop mutable", &options(QueryMode::Or)).unwrap();
+        assert_eq!(hits.len(), 3);
+    }
+
+    #[test]
+    fn test_stop_word_only_query_has_no_hits() {
+        let ctx = indexed_ctx("stop-words");
+        assert!(query_rdb(&ctx, "the for a", &options(QueryMode::Or)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_terms_fall_back_to_similar_indexed_terms() {
+        let ctx = indexed_ctx("fuzzy");
+        // keys outside the postings must not be picked up, whatever their bytes
+        ctx.rocks_con.put(b"zz:mutabl\xff", b"").unwrap();
+
+        let hits = query_rdb(&ctx, "mutabl", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/lists"]);
+    }
+
+    #[test]
+    fn test_rankers_prefer_documents_matching_more_terms() {
+        let ctx = indexed_ctx("rankers");
+        for ranking in [Ranking::TfIdf, Ranking::default(), Ranking::Bm25F(Bm25F::default())] {
+            let opts = SearchOptions { mode: QueryMode::Or, ranking, ..SearchOptions::default() };
+            let hits = query_rdb(&ctx, "python loop", &opts).unwrap();
+            assert_eq!(hits.len(), 3);
+            assert_eq!(hits[2].url, "https://example.com/lists");
+            assert!(hits[0].score > hits[2].score);
         }
     }
-    Ok(String::new())
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_phrase_query_requires_adjacent_terms() {
+        let ctx = positional_ctx("phrase");
+        let hits = query_rdb(&ctx, r#""list comprehension""#, &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/comprehensions"]);
+
+        let hits = query_rdb(&ctx, r#""comprehension list""#, &options(QueryMode::And)).unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_near_query() {
+        let ctx = indexed_ctx("near");
+        let hits = query_rdb(&ctx, "python NEAR/1 loop", &options(QueryMode::And)).unwrap();
+        assert!(hits.is_empty());
+
+        let hits = query_rdb(&ctx, "python NEAR/2 loop", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/for", "https://example.com/while"]);
+    }
+
+    #[test]
+    fn test_not_excludes_documents() {
+        let ctx = positional_ctx("not");
+        let hits = query_rdb(&ctx, "python -loop", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/comprehensions", "https://example.com/lists"]);
+
+        let hits = query_rdb(&ctx, "NOT python", &options(QueryMode::And)).unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_boolean_grouping() {
+        let ctx = positional_ctx("grouping");
+        let hits = query_rdb(&ctx, "python (mutable OR generators) NOT list", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/lists"]);
+    }
+
+    #[test]
+    fn test_field_and_site_prefixes() {
+        let ctx = indexed_ctx("prefixes");
+        let hits = query_rdb(&ctx, "title:loop", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/for", "https://example.com/while"]);
+
+        let hits = query_rdb(&ctx, "title:python", &options(QueryMode::And)).unwrap();
+        assert!(hits.is_empty());
+
+        let hits = query_rdb(&ctx, "site:example.com url:lists", &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/lists"]);
+
+        assert!(query_rdb(&ctx, "python site:python.org", &options(QueryMode::And)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_field_phrase_stays_inside_the_field() {
+        let ctx = TestCtx::new("search-field-phrase");
+        index_pages(&ctx, &[
+            ("https://example.com/intro", "Learn python", "Lists come first."),
+            ("https://example.com/sorting", "Sorting lists with python", "Python lists sort in place.")
+        ]);
+
+        assert!(query_rdb(&ctx, r#"title:"python lists""#, &options(QueryMode::And)).unwrap().is_empty());
+        let hits = query_rdb(&ctx, r#"body:"python lists""#, &options(QueryMode::And)).unwrap();
+        assert_eq!(urls(&hits), vec!["https://example.com/sorting"]);
+    }
+
+    #[test]
+    fn test_field_terms_are_scored_on_their_field() {
+        let ctx = TestCtx::new("search-field-scores");
+        index_pages(&ctx, &[
+            ("https://example.com/basics", "Python basics", "Start here."),
+            ("https://example.com/notes", "Notes on python and other languages", "Python python python, python and more python.")
+        ]);
+
+        let hits = query_rdb(&ctx, "python", &options(QueryMode::And)).unwrap();
+        assert_eq!(hits[0].url, "https://example.com/notes");
+
+        // only the title counts, where python is one word out of fewer
+        let hits = query_rdb(&ctx, "title:python", &options(QueryMode::And)).unwrap();
+        assert_eq!(hits[0].url, "https://example.com/basics");
+    }
+
+    #[test]
+    fn test_parse_errors_surface() {
+        let ctx = indexed_ctx("parse-error");
+        let err = query_rdb(&ctx, "python AND (loop", &options(QueryMode::And)).unwrap_err();
+        assert!(matches!(err, SearchError::Parse(_)));
+    }
+
+    #[test]
+    fn test_proximity_boost_changes_ranking() {
+        let ctx = positional_ctx("proximity");
+        let close = query_rdb(&ctx, "list generators", &options(QueryMode::And)).unwrap();
+        let no_boost = SearchOptions { proximity_weight: 0.0, ..options(QueryMode::And) };
+        let far = query_rdb(&ctx, "list generators", &no_boost).unwrap();
+        assert!(close[0].score > far[0].score);
+    }
+
+    #[test]
+    fn test_pagerank_is_mixed_into_ranking() {
+        let ctx = indexed_ctx("pagerank");
+        let reader = IndexReader::new(&ctx);
+        let for_loop: u64 = reader.doc_id("https://example.com/for").unwrap().unwrap();
+        let while_loop: u64 = reader.doc_id("https://example.com/while").unwrap().unwrap();
+        Indexer::new(ctx.clone()).set_pageranks(&[(for_loop, 0.2), (while_loop, 20.0)]).unwrap();
+
+        let text_only = SearchOptions { pagerank_weight: 0.0, ..options(QueryMode::And) };
+        assert_eq!(query_rdb(&ctx, "python for loop", &text_only).unwrap()[0].url, "https://example.com/for");
+
+        let hits = query_rdb(&ctx, "python for loop", &options(QueryMode::And)).unwrap();
+        assert_eq!(hits[0].url, "https://example.com/while");
+    }
+}
@@ -1,8 +1,12 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
+
+use indexer::Field;
+
+use crate::QueryMode;
 
 pub fn parse_query_params(url: &str) -> HashMap<String, String> {
     let mut params: HashMap<String, String> = HashMap::new();
-    
+
     if let Some(query_string) = url.split('?').nth(1) {
         for pair in query_string.split('&') {
             let mut key_value = pair.splitn(2, '=');
@@ -12,4 +16,497 @@ pub fn parse_query_params(url: &str) -> HashMap<String, String> {
         }
     }
     params
-}
\ No newline at end of file
+}
+
+/// Word distance used by a bare `NEAR` without `/k`.
+pub const DEFAULT_NEAR_DISTANCE: u32 = 10;
+
+/// Parsed search query. Terms are already run through the word
+/// processing pipeline, so they compare equal to indexed terms.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// A single term, optionally restricted to one field (`title:python`).
+    Term {
+        term: String,
+        field: Option<Field>
+    },
+
+    /// A quoted phrase: its terms with their offsets from the first word.
+    Phrase {
+        terms: Vec<(String, u32)>,
+        field: Option<Field>
+    },
+
+    /// `left NEAR/distance right`
+    Near {
+        left: String,
+        right: String,
+        distance: u32
+    },
+
+    /// `site:docs.python.org` matches the host and its subdomains.
+    Site(String),
+
+    /// `url:reference` matches any URL containing the value.
+    Url(String),
+
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>)
+}
+
+impl Query {
+    /// Terms a matching document is expected to contain, i.e. every term
+    /// outside a `NOT`. Used to pick snippets.
+    pub fn terms(&self) -> Vec<String> {
+        match self {
+            Query::Term { term, .. } => vec![term.clone()],
+            Query::Phrase { terms, .. } => terms.iter().map(|(term, _)| term.clone()).collect(),
+            Query::Near { left, right, .. } => vec![left.clone(), right.clone()],
+            Query::Site(_) | Query::Url(_) | Query::Not(_) => vec![],
+            Query::And(children) | Query::Or(children) => children.iter().flat_map(Query::terms).collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset into the query where the problem was found.
+    pub position: usize,
+    pub message: String
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self { position, message: message.into() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+
+    /// `title:`, `site:`... directly followed by its value.
+    Prefix(String),
+    And,
+    Or,
+    Not,
+    Near(u32),
+    LParen,
+    RParen
+}
+
+const PREFIXES: [&str; 7] = ["title", "headings", "description", "body", "anchor", "site", "url"];
+
+fn is_word_char(ch: char) -> bool {
+    !ch.is_whitespace() && !matches!(ch, '(' | ')' | '"')
+}
+
+fn lex(input: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens: Vec<(Token, usize)> = vec![];
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, ch)) = chars.peek() {
+        match ch {
+            _ if ch.is_whitespace() => {
+                chars.next();
+            },
+            '(' => {
+                chars.next();
+                tokens.push((Token::LParen, start));
+            },
+            ')' => {
+                chars.next();
+                tokens.push((Token::RParen, start));
+            },
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, ch)) => phrase.push(ch),
+                        None => return Err(ParseError::new(start, "unterminated quote"))
+                    }
+                }
+                tokens.push((Token::Phrase(phrase), start));
+            },
+            '-' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, next)) if is_word_char(next) || next == '"' || next == '(' => {
+                        tokens.push((Token::Not, start));
+                    },
+                    _ => tokens.push((Token::Word("-".to_string()), start))
+                }
+            },
+            _ => {
+                let mut word = String::new();
+                while let Some(&(_, ch)) = chars.peek() {
+                    if !is_word_char(ch) {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+
+                    if ch == ':' && PREFIXES.contains(&word.trim_end_matches(':').to_lowercase().as_str()) {
+                        break;
+                    }
+                }
+                tokens.push((word_token(word, start)?, start));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn word_token(word: String, position: usize) -> Result<Token, ParseError> {
+    if let Some(prefix) = word.strip_suffix(':') {
+        if PREFIXES.contains(&prefix.to_lowercase().as_str()) {
+            return Ok(Token::Prefix(prefix.to_lowercase()));
+        }
+    }
+
+    let token = match word.as_str() {
+        "AND" => Token::And,
+        "OR" => Token::Or,
+        "NOT" => Token::Not,
+        "NEAR" => Token::Near(DEFAULT_NEAR_DISTANCE),
+        _ => match word.strip_prefix("NEAR/") {
+            Some(distance) => Token::Near(distance.parse::<u32>().map_err(|_| {
+                ParseError::new(position + 5, format!("expected a word distance after NEAR/, found '{}'", distance))
+            })?),
+            None => Token::Word(word)
+        }
+    };
+    Ok(token)
+}
+
+/// How two operands were joined in an AND sequence.
+enum Joint {
+    Explicit,
+    Implicit
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    cursor: usize,
+    end: usize,
+
+    /// Operator used between terms written next to each other.
+    default_mode: QueryMode
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.cursor).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.cursor).map_or(self.end, |(_, position)| *position)
+    }
+
+    fn advance(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.cursor).cloned();
+        self.cursor += 1;
+        token
+    }
+
+    fn starts_operand(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token::Word(_) | Token::Phrase(_) | Token::Prefix(_) | Token::Not | Token::LParen)
+        )
+    }
+
+    fn expect_operand(&self, message: &str) -> Result<(), ParseError> {
+        if self.starts_operand() {
+            Ok(())
+        } else {
+            Err(ParseError::new(self.position(), message))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Option<Query>, ParseError> {
+        let mut operands: Vec<Query> = vec![];
+        operands.extend(self.parse_and()?);
+
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            self.expect_operand("expected a term after OR")?;
+            operands.extend(self.parse_and()?);
+        }
+        Ok(combine(operands, Query::Or))
+    }
+
+    fn parse_and(&mut self) -> Result<Option<Query>, ParseError> {
+        self.expect_operand("expected a term")?;
+
+        let mut operands: Vec<(Joint, Option<Query>)> = vec![(Joint::Explicit, self.parse_unary()?)];
+        loop {
+            if self.peek() == Some(&Token::And) {
+                self.advance();
+                self.expect_operand("expected a term after AND")?;
+                operands.push((Joint::Explicit, self.parse_unary()?));
+            } else if self.starts_operand() {
+                operands.push((Joint::Implicit, self.parse_unary()?));
+            } else {
+                break;
+            }
+        }
+
+        if self.default_mode == QueryMode::And {
+            return Ok(combine(operands.into_iter().filter_map(|(_, query)| query).collect(), Query::And));
+        }
+
+        // implicit joints are ORs: split on them, explicit ANDs bind tighter
+        let mut groups: Vec<Vec<Query>> = vec![];
+        for (joint, query) in operands {
+            if matches!(joint, Joint::Implicit) || groups.is_empty() {
+                groups.push(vec![]);
+            }
+            groups.last_mut().unwrap().extend(query);
+        }
+        let groups: Vec<Query> = groups.into_iter().filter_map(|group| combine(group, Query::And)).collect();
+        Ok(combine(groups, Query::Or))
+    }
+
+    fn parse_unary(&mut self) -> Result<Option<Query>, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            self.expect_operand("expected a term after NOT")?;
+            return Ok(self.parse_unary()?.map(|query| Query::Not(Box::new(query))));
+        }
+        self.parse_near()
+    }
+
+    fn parse_near(&mut self) -> Result<Option<Query>, ParseError> {
+        let left_position: usize = self.position();
+        let mut left: Option<Query> = self.parse_primary()?;
+        let mut pairs: Vec<Query> = vec![];
+
+        while let Some(Token::Near(distance)) = self.peek().cloned() {
+            self.advance();
+            self.expect_operand("expected a word after NEAR")?;
+            let right_position: usize = self.position();
+            let right: Option<Query> = self.parse_primary()?;
+
+            let left_term = Self::near_operand(&left, left_position)?;
+            let right_term = Self::near_operand(&right, right_position)?;
+            if let (Some(left), Some(right)) = (left_term, right_term) {
+                pairs.push(Query::Near { left, right, distance });
+            }
+            left = right;
+        }
+
+        if pairs.is_empty() {
+            Ok(left)
+        } else {
+            Ok(combine(pairs, Query::And))
+        }
+    }
+
+    /// NEAR only relates single, unrestricted words. Stop words vanish.
+    fn near_operand(query: &Option<Query>, position: usize) -> Result<Option<String>, ParseError> {
+        match query {
+            Some(Query::Term { term, field: None }) => Ok(Some(term.clone())),
+            None => Ok(None),
+            Some(_) => Err(ParseError::new(position, "NEAR needs a single word on both sides"))
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Option<Query>, ParseError> {
+        let Some((token, position)) = self.advance() else {
+            return Err(ParseError::new(self.end, "unexpected end of query"));
+        };
+
+        match token {
+            Token::LParen => {
+                let inner = self.parse_or()?;
+                match self.advance() {
+                    Some((Token::RParen, _)) => Ok(inner),
+                    _ => Err(ParseError::new(position, "missing closing parenthesis"))
+                }
+            },
+            Token::Word(word) => Ok(text_query(&word, None)),
+            Token::Phrase(phrase) => Ok(text_query(&phrase, None)),
+            Token::Prefix(prefix) => {
+                // the value has to follow the colon directly
+                let value_position: usize = position + prefix.len() + 1;
+                let value: String = match self.advance() {
+                    Some((Token::Word(value) | Token::Phrase(value), at)) if at == value_position => value,
+                    _ => return Err(ParseError::new(position, format!("expected a value after '{}:'", prefix)))
+                };
+
+                match prefix.as_str() {
+                    "site" => Ok(Some(Query::Site(value.to_lowercase().trim_start_matches("www.").to_string()))),
+                    "url" => Ok(Some(Query::Url(value))),
+                    _ => {
+                        let field = Field::ALL.into_iter().find(|field| field.name() == prefix);
+                        Ok(text_query(&value, field))
+                    }
+                }
+            },
+            other => Err(ParseError::new(position, format!("unexpected {}", describe(&other))))
+        }
+    }
+}
+
+fn describe(token: &Token) -> &'static str {
+    match token {
+        Token::And => "AND",
+        Token::Or => "OR",
+        Token::Not => "NOT",
+        Token::Near(_) => "NEAR",
+        Token::RParen => "')'",
+        Token::LParen => "'('",
+        Token::Word(_) | Token::Phrase(_) | Token::Prefix(_) => "term"
+    }
+}
+
+fn combine(mut operands: Vec<Query>, node: fn(Vec<Query>) -> Query) -> Option<Query> {
+    match operands.len() {
+        0 => None,
+        1 => operands.pop(),
+        _ => Some(node(operands))
+    }
+}
+
+/// A word or phrase after the word processing pipeline. Words that split
+/// into several terms (`list-comprehension`) become a phrase.
+fn text_query(text: &str, field: Option<Field>) -> Option<Query> {
+    let tokens = wp::tokenize(text);
+    let first: u32 = tokens.first().map_or(0, |token| token.position as u32);
+    let mut terms: Vec<(String, u32)> = tokens.into_iter()
+        .map(|token| (token.term, token.position as u32 - first))
+        .collect();
+
+    match terms.len() {
+        0 => None,
+        1 => Some(Query::Term { term: terms.pop().unwrap().0, field }),
+        _ => Some(Query::Phrase { terms, field })
+    }
+}
+
+/// Parses the query language:
+///
+/// - `python generators`: terms joined by `default_mode`
+/// - `AND`, `OR`, `NOT` / `-term` and parentheses
+/// - `"list comprehension"`: exact phrase
+/// - `python NEAR/3 loop`: words at most 3 positions apart
+/// - `title:`, `headings:`, `description:`, `body:`, `anchor:` restrict a
+///   term or phrase to one field; `site:` and `url:` filter documents
+///
+/// Returns `None` when nothing searchable is left, e.g. only stop words.
+pub fn parse_query(input: &str, default_mode: QueryMode) -> Result<Option<Query>, ParseError> {
+    let tokens = lex(input)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        cursor: 0,
+        end: input.len(),
+        default_mode
+    };
+
+    let query = parser.parse_or()?;
+    if let Some((token, position)) = parser.advance() {
+        return Err(ParseError::new(position, format!("unexpected {}", describe(&token))));
+    }
+    Ok(query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: &str) -> Query {
+        Query::Term { term: term.to_string(), field: None }
+    }
+
+    fn parse(input: &str) -> Option<Query> {
+        parse_query(input, QueryMode::And).unwrap()
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(parse("python AND generator OR iterator"), Some(Query::Or(vec![
+            Query::And(vec![term("python"), term("generator")]),
+            term("iterator")
+        ])));
+        assert_eq!(parse("python (generator OR iterator)"), Some(Query::And(vec![
+            term("python"),
+            Query::Or(vec![term("generator"), term("iterator")])
+        ])));
+    }
+
+    #[test]
+    fn test_implicit_operator_follows_default_mode() {
+        assert_eq!(parse_query("python AND generator iterator", QueryMode::Or).unwrap(), Some(Query::Or(vec![
+            Query::And(vec![term("python"), term("generator")]),
+            term("iterator")
+        ])));
+    }
+
+    #[test]
+    fn test_negation_phrases_and_fields() {
+        assert_eq!(parse(r#"-java title:"list comprehension" site:www.Python.org NOT url:wiki"#), Some(Query::And(vec![
+            Query::Not(Box::new(term("java"))),
+            Query::Phrase {
+                terms: vec![("list".to_string(), 0), ("comprehension".to_string(), 1)],
+                field: Some(Field::Title)
+            },
+            Query::Site("python.org".to_string()),
+            Query::Not(Box::new(Query::Url("wiki".to_string())))
+        ])));
+    }
+
+    #[test]
+    fn test_near() {
+        assert_eq!(parse("python NEAR/3 generator"), Some(Query::Near {
+            left: "python".to_string(),
+            right: "generator".to_string(),
+            distance: 3
+        }));
+        assert_eq!(parse("python NEAR generator"), Some(Query::Near {
+            left: "python".to_string(),
+            right: "generator".to_string(),
+            distance: DEFAULT_NEAR_DISTANCE
+        }));
+    }
+
+    #[test]
+    fn test_stop_words_vanish() {
+        assert_eq!(parse("the"), None);
+        assert_eq!(parse("the python"), Some(term("python")));
+        assert_eq!(parse(r#""list of lists""#), Some(Query::Phrase {
+            terms: vec![("list".to_string(), 0), ("lists".to_string(), 2)],
+            field: None
+        }));
+    }
+
+    #[test]
+    fn test_errors_carry_positions() {
+        let error = |input: &str| parse_query(input, QueryMode::And).unwrap_err();
+
+        assert_eq!(error("python AND").position, 10);
+        assert_eq!(error("(python OR java").position, 0);
+        assert_eq!(error("python) java").position, 6);
+        assert_eq!(error(r#"python "loop"#).position, 7);
+        assert_eq!(error("title: python").position, 0);
+        assert_eq!(error("python NEAR/x loop").position, 12);
+        assert_eq!(error(r#""list comprehension" NEAR/2 python"#).position, 0);
+        assert_eq!(error("OR python").to_string(), "expected a term at position 0");
+    }
+}
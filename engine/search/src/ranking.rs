@@ -0,0 +1,301 @@
+use indexer::{CollectionTotals, Field, FieldCounts, IndexReader};
+
+/// What is known about one query term in one document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TermStats {
+    pub term_freq: u32,
+    pub doc_length: u32,
+
+    /// Number of documents the term appears in.
+    pub doc_freq: usize,
+
+    pub field_freqs: FieldCounts,
+    pub field_lengths: FieldCounts
+}
+
+/// Index-wide statistics shared by every term of a query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollectionStats {
+    pub total_docs: usize,
+    pub avg_doc_length: f32,
+
+    /// Average length of each field, indexed by `Field::index`.
+    pub avg_field_lengths: [f32; Field::COUNT]
+}
+
+impl CollectionStats {
+    /// Reads the totals the indexer keeps next to the index. `fallback`
+    /// is used for whatever can't be read or is still zero.
+    pub fn from_index(reader: &IndexReader, fallback: CollectionStats) -> Self {
+        let totals: CollectionTotals = reader.collection_totals().unwrap_or_default();
+        let total_docs: usize = match totals.docs {
+            0 => fallback.total_docs,
+            docs => docs as usize
+        };
+
+        let avg_doc_length: f32 = match totals.length {
+            0 => fallback.avg_doc_length,
+            length => length as f32 / total_docs.max(1) as f32
+        };
+
+        let mut avg_field_lengths: [f32; Field::COUNT] = fallback.avg_field_lengths;
+        for field in Field::ALL {
+            let total: u64 = totals.field_lengths[field.index()];
+            if total > 0 {
+                avg_field_lengths[field.index()] = total as f32 / total_docs.max(1) as f32;
+            }
+        }
+
+        Self {
+            total_docs,
+            avg_doc_length,
+            avg_field_lengths
+        }
+    }
+}
+
+/// Scores one term of a query against one document. A document's score
+/// for the whole query is the sum over its matching terms.
+pub trait Ranker: Send + Sync {
+    fn score(&self, term: &TermStats, collection: &CollectionStats) -> f32;
+}
+
+/// Length-normalized term frequency times `log10(N / df)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TfIdf;
+
+impl Ranker for TfIdf {
+    fn score(&self, term: &TermStats, collection: &CollectionStats) -> f32 {
+        let tf: f32 = term.term_freq as f32 / term.doc_length.max(1) as f32;
+        let idf: f32 = (collection.total_docs.max(term.doc_freq) as f32 / term.doc_freq.max(1) as f32).log10();
+        tf * idf
+    }
+}
+
+/// Okapi BM25.
+///
+/// `k1` controls how quickly repeated occurrences of a term stop adding
+/// to the score, `b` how strongly long documents are penalized.
+#[derive(Debug, Clone, Copy)]
+pub struct Bm25 {
+    pub k1: f32,
+    pub b: f32
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Bm25 {
+    pub(crate) fn idf(doc_freq: usize, total_docs: usize) -> f32 {
+        let n: f32 = total_docs.max(doc_freq) as f32;
+        let df: f32 = doc_freq as f32;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+}
+
+impl Ranker for Bm25 {
+    fn score(&self, term: &TermStats, collection: &CollectionStats) -> f32 {
+        let tf: f32 = term.term_freq as f32;
+        let length_ratio: f32 = term.doc_length as f32 / collection.avg_doc_length.max(1.0);
+        let norm: f32 = self.k1 * (1.0 - self.b + self.b * length_ratio);
+
+        Self::idf(term.doc_freq, collection.total_docs) * (tf * (self.k1 + 1.0)) / (tf + norm)
+    }
+}
+
+/// Relative importance of a match in each field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldWeights {
+    pub title: f32,
+    pub headings: f32,
+    pub description: f32,
+    pub body: f32,
+    pub anchor: f32
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        Self {
+            title: 3.0,
+            headings: 2.0,
+            description: 1.5,
+            body: 1.0,
+            anchor: 2.0
+        }
+    }
+}
+
+impl FieldWeights {
+    pub fn weight(&self, field: Field) -> f32 {
+        match field {
+            Field::Title => self.title,
+            Field::Headings => self.headings,
+            Field::Description => self.description,
+            Field::Body => self.body,
+            Field::Anchor => self.anchor
+        }
+    }
+}
+
+/// BM25F: BM25 over a weighted sum of per-field term frequencies, each
+/// normalized by the length of its own field.
+#[derive(Debug, Clone, Copy)]
+pub struct Bm25F {
+    pub k1: f32,
+    pub b: f32,
+    pub weights: FieldWeights
+}
+
+impl Default for Bm25F {
+    fn default() -> Self {
+        let bm25 = Bm25::default();
+        Self {
+            k1: bm25.k1,
+            b: bm25.b,
+            weights: FieldWeights::default()
+        }
+    }
+}
+
+impl Ranker for Bm25F {
+    fn score(&self, term: &TermStats, collection: &CollectionStats) -> f32 {
+        let mut weighted_tf: f32 = 0.0;
+
+        for field in Field::ALL {
+            let tf: f32 = term.field_freqs[field.index()] as f32;
+            if tf == 0.0 {
+                continue;
+            }
+
+            let avg_length: f32 = collection.avg_field_lengths[field.index()].max(1.0);
+            let length_ratio: f32 = term.field_lengths[field.index()] as f32 / avg_length;
+            weighted_tf += self.weights.weight(field) * tf / (1.0 - self.b + self.b * length_ratio);
+        }
+
+        Bm25::idf(term.doc_freq, collection.total_docs) * weighted_tf / (self.k1 + weighted_tf)
+    }
+}
+
+/// Score multiplier for a document with PageRank `rank`, scaled so the
+/// average page has 1.0 and gets no boost. Better linked pages gain up to
+/// about `weight` per doubling of their rank, worse linked ones lose.
+pub fn pagerank_boost(rank: f32, weight: f32) -> f32 {
+    (1.0 + weight * rank.max(0.0).ln_1p()) / (1.0 + weight * std::f32::consts::LN_2)
+}
+
+/// Ranking function picked per request.
+#[derive(Debug, Clone, Copy)]
+pub enum Ranking {
+    TfIdf,
+    Bm25(Bm25),
+    Bm25F(Bm25F)
+}
+
+impl Default for Ranking {
+    fn default() -> Self {
+        Ranking::Bm25(Bm25::default())
+    }
+}
+
+impl Ranking {
+    pub fn ranker(&self) -> Box<dyn Ranker> {
+        match self {
+            Ranking::TfIdf => Box::new(TfIdf),
+            Ranking::Bm25(bm25) => Box::new(*bm25),
+            Ranking::Bm25F(bm25f) => Box::new(*bm25f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLECTION: CollectionStats = CollectionStats {
+        total_docs: 100,
+        avg_doc_length: 50.0,
+        avg_field_lengths: [5.0, 5.0, 10.0, 30.0, 5.0]
+    };
+
+    fn stats(term_freq: u32, doc_length: u32, doc_freq: usize) -> TermStats {
+        TermStats {
+            term_freq,
+            doc_length,
+            doc_freq,
+            field_freqs: [0, 0, 0, term_freq, 0],
+            field_lengths: [0, 0, 0, doc_length, 0]
+        }
+    }
+
+    fn field_stats(field: Field, term_freq: u32) -> TermStats {
+        let mut field_freqs: FieldCounts = [0; Field::COUNT];
+        field_freqs[field.index()] = term_freq;
+        TermStats {
+            term_freq,
+            doc_length: 50,
+            doc_freq: 10,
+            field_freqs,
+            field_lengths: [5, 5, 10, 30, 0]
+        }
+    }
+
+    #[test]
+    fn test_bm25_saturates_term_frequency() {
+        let bm25 = Bm25::default();
+        let once = bm25.score(&stats(1, 50, 10), &COLLECTION);
+        let ten = bm25.score(&stats(10, 50, 10), &COLLECTION);
+        let hundred = bm25.score(&stats(100, 50, 10), &COLLECTION);
+
+        assert!(ten > once);
+        assert!(hundred - ten < ten - once);
+        assert!(hundred < (bm25.k1 + 1.0) * Bm25::idf(10, 100));
+    }
+
+    #[test]
+    fn test_bm25_penalizes_long_documents() {
+        let bm25 = Bm25::default();
+        let short = bm25.score(&stats(3, 20, 10), &COLLECTION);
+        let long = bm25.score(&stats(3, 500, 10), &COLLECTION);
+        assert!(short > long);
+
+        let no_norm = Bm25 { b: 0.0, ..Bm25::default() };
+        assert_eq!(no_norm.score(&stats(3, 20, 10), &COLLECTION), no_norm.score(&stats(3, 500, 10), &COLLECTION));
+    }
+
+    #[test]
+    fn test_rare_terms_score_higher() {
+        for ranking in [Ranking::TfIdf, Ranking::default(), Ranking::Bm25F(Bm25F::default())] {
+            let ranker = ranking.ranker();
+            let rare = ranker.score(&stats(2, 50, 2), &COLLECTION);
+            let common = ranker.score(&stats(2, 50, 80), &COLLECTION);
+            assert!(rare > common);
+        }
+    }
+
+    #[test]
+    fn test_pagerank_boost() {
+        assert!((pagerank_boost(1.0, 0.5) - 1.0).abs() < 1e-6);
+        assert!(pagerank_boost(10.0, 0.5) > 1.0);
+        assert!(pagerank_boost(0.1, 0.5) < 1.0);
+        assert_eq!(pagerank_boost(10.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_bm25f_weights_fields() {
+        let bm25f = Bm25F::default();
+        let in_title = bm25f.score(&field_stats(Field::Title, 1), &COLLECTION);
+        let in_body = bm25f.score(&field_stats(Field::Body, 1), &COLLECTION);
+        assert!(in_title > in_body);
+
+        let body_first = Bm25F {
+            weights: FieldWeights { title: 0.2, body: 5.0, ..FieldWeights::default() },
+            ..bm25f
+        };
+        let in_title = body_first.score(&field_stats(Field::Title, 1), &COLLECTION);
+        let in_body = body_first.score(&field_stats(Field::Body, 1), &COLLECTION);
+        assert!(in_body > in_title);
+    }
+}
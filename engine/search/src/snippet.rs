@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+
+/// Number of words `snippet` returns when the text is longer.
+pub const SNIPPET_WORDS: usize = 30;
+
+/// The stretch of `max_words` words of `text` holding the most
+/// occurrences of `terms`, centered on them. `terms` are processed terms
+/// as they come out of `parse_query`; words are compared after running
+/// through the same pipeline.
+pub fn snippet(text: &str, terms: &[String], max_words: usize) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() <= max_words {
+        return words.join(" ");
+    }
+
+    let terms: HashSet<&str> = terms.iter().map(String::as_str).collect();
+    let hits: Vec<bool> = words.iter()
+        .map(|word| wp::tokenize(word).iter().any(|token| terms.contains(token.term.as_str())))
+        .collect();
+
+    let mut best: (usize, usize) = (0, 0);
+    let mut count: usize = hits[..max_words].iter().filter(|hit| **hit).count();
+    best.1 = count;
+    for start in 1..=words.len() - max_words {
+        count = count + hits[start + max_words - 1] as usize - hits[start - 1] as usize;
+        if count > best.1 {
+            best = (start, count);
+        }
+    }
+
+    let window = best.0..best.0 + max_words;
+    let first_hit: Option<usize> = window.clone().find(|i| hits[*i]);
+    let last_hit: Option<usize> = window.clone().rev().find(|i| hits[*i]);
+    let start: usize = match (first_hit, last_hit) {
+        (Some(first), Some(last)) => ((first + last) / 2).saturating_sub(max_words / 2).min(words.len() - max_words),
+        _ => 0
+    };
+
+    let mut snippet: String = words[start..start + max_words].join(" ");
+    if start > 0 {
+        snippet.insert_str(0, "… ");
+    }
+    if start + max_words < words.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_text_is_returned_whole() {
+        assert_eq!(snippet("A for  loop\niterates.", &[], 10), "A for loop iterates.");
+    }
+
+    #[test]
+    fn test_snippet_centers_on_matches() {
+        let filler: Vec<String> = (0..40).map(|i| format!("word{}", i)).collect();
+        let text = format!("{} Generators yield values lazily. {}", filler[..20].join(" "), filler[20..].join(" "));
+
+        let terms: Vec<String> = wp::tokenize("generators lazily").into_iter().map(|token| token.term).collect();
+        let snippet = snippet(&text, &terms, 8);
+        assert!(snippet.starts_with("… "));
+        assert!(snippet.ends_with(" …"));
+        assert!(snippet.contains("Generators yield values lazily."));
+    }
+}
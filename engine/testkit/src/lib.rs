@@ -0,0 +1,72 @@
+//! Fixtures shared by the engine's tests.
+
+use std::{
+    ops::Deref,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc
+    }
+};
+
+use context::{CTXOptions, CTX};
+
+/// Number of contexts created by this process so far.
+static CONTEXTS: AtomicUsize = AtomicUsize::new(0);
+
+/// A context on a fresh RocksDB directory, which is removed on drop.
+pub struct TestCtx {
+    ctx: Arc<CTX>,
+    path: PathBuf
+}
+
+impl TestCtx {
+    pub fn new(name: &str) -> Self {
+        let id = format!("{}-{}-{}", name, std::process::id(), CONTEXTS.fetch_add(1, Ordering::SeqCst));
+        let path: PathBuf = std::env::temp_dir().join(format!("spy-test-{}", id));
+        _ = std::fs::remove_dir_all(&path);
+
+        let options = CTXOptions::default().rocks_db_name(path.to_string_lossy());
+        Self { ctx: Arc::new(CTX::new(options)), path }
+    }
+
+    /// A fresh context and what `build` makes of it, such as an indexer,
+    /// which must not outlive the context.
+    pub fn with<T>(name: &str, build: impl FnOnce(Arc<CTX>) -> T) -> (Self, T) {
+        let ctx = Self::new(name);
+        let built: T = build(ctx.ctx.clone());
+        (ctx, built)
+    }
+}
+
+impl Deref for TestCtx {
+    type Target = Arc<CTX>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ctx
+    }
+}
+
+impl Drop for TestCtx {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Answers every request to a local port with `handler` on a thread of
+/// its own, and returns the server's origin, like `http://127.0.0.1:1234`.
+pub fn serve<F>(handler: F) -> String
+where
+    F: Fn(&tiny_http::Request) -> tiny_http::ResponseBox + Send + 'static
+{
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let origin = format!("http://{}", server.server_addr());
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = handler(&request);
+            _ = request.respond(response);
+        }
+    });
+    origin
+}
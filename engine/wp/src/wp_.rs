@@ -2,6 +2,8 @@ use std::collections::HashSet;
 
 use lazy_static::lazy_static;
 
+use crate::stem::stem;
+
 lazy_static! {
     static ref STOP_WORDS: HashSet<&'static str> = {
         HashSet::from([
@@ -58,11 +60,19 @@ lazy_static! {
         }
         special_chars
     };
+
+    static ref NON_ALPHA: regex::Regex = regex::Regex::new(r"[^a-zA-Z]").unwrap();
+}
+
+/// A processed term together with the position of the word it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub term: String,
+    pub position: usize
 }
 
 fn remove_special_characters(word: &str) -> String {
-    let re = regex::Regex::new(r"[^a-zA-Z]").unwrap();
-    let no_spec: String = re.replace_all(word, " ").trim().to_string();
+    let no_spec: String = NON_ALPHA.replace_all(word, " ").trim().to_string();
     if no_spec.contains(" ") {
         no_spec.split_whitespace().collect::<Vec<&str>>().first().unwrap().to_string()
     } else {
@@ -89,4 +99,48 @@ pub fn filter_stop_words(word_list: Vec<&str>) -> Vec<&str> {
             !STOP_WORDS.contains(lower_word.as_str())
         }
     ).collect()
-}
\ No newline at end of file
+}
+
+/// Runs text through the whole word processing pipeline.
+/// 
+/// Words are split on anything that isn't alphanumeric, so `list-comprehension`
+/// yields two tokens. Positions count every word, stop words included, which
+/// keeps the distance between two surviving terms faithful to the source text.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = vec![];
+
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty());
+
+    for (position, word) in words.enumerate() {
+        let finalized: Vec<String> = finalize_word_list(vec![word]);
+        let non_stop_words: Vec<&str> = filter_stop_words(finalized.iter().map(AsRef::as_ref).collect());
+
+        for term in stem(non_stop_words) {
+            if !term.is_empty() {
+                tokens.push(Token { term: term.to_string(), position });
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_drops_stop_words_but_keeps_positions() {
+        let tokens = tokenize("The loop is running");
+        let terms: Vec<(&str, usize)> = tokens.iter().map(|t| (t.term.as_str(), t.position)).collect();
+        assert_eq!(terms, vec![("loop", 1), ("runn", 3)]);
+    }
+
+    #[test]
+    fn test_tokenize_splits_on_punctuation() {
+        let tokens = tokenize("list-comprehension, (Python)");
+        let terms: Vec<&str> = tokens.iter().map(|t| t.term.as_str()).collect();
+        assert_eq!(terms, vec!["list", "comprehension", "python"]);
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Instant
+};
+
+use context::CTX;
+use crawler::{
+    errors::JobError, 
+    CrawlerOptions, 
+    JobRegistry, 
+    JobStatus, 
+    ScopeMode, 
+    ScopeOptions
+};
+use regex::Regex;
+use search::{
+    errors::SearchError,
+    search_page,
+    Bm25F,
+    QueryMode,
+    Ranking,
+    SearchOptions,
+    SearchPage
+};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PER_PAGE: usize = 10;
+pub const MAX_PER_PAGE: usize = 100;
+
+/// Response of `GET /search`.
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    query: String,
+    results: Vec<SearchResultJson>,
+    total_hits: usize,
+    page: usize,
+    per_page: usize,
+
+    /// Time spent answering the query, in milliseconds.
+    took_ms: f64
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResultJson {
+    url: String,
+    title: String,
+    snippet: String,
+    score: f32
+}
+
+/// Body of `POST /crawls`.
+#[derive(Debug, Deserialize)]
+struct StartCrawlRequest {
+    seed_url: String,
+
+    #[serde(default = "default_max_depth")]
+    max_depth: usize,
+
+    /// `same_host`, `same_domain` or `any`.
+    #[serde(default)]
+    scope: Option<String>,
+
+    /// Regexes, see `ScopeOptions`.
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+
+    #[serde(default)]
+    max_pages_per_host: Option<usize>,
+
+    /// Resumes the crawl checkpointed under this id.
+    #[serde(default)]
+    job_id: Option<u64>
+}
+
+impl StartCrawlRequest {
+    fn scope_options(&self) -> Result<ScopeOptions, String> {
+        let mode: ScopeMode = match self.scope.as_deref() {
+            None | Some("same_host") => ScopeMode::SameHost,
+            Some("same_domain") => ScopeMode::SameDomain,
+            Some("any") => ScopeMode::Any,
+            Some(other) => return Err(format!("unknown scope '{}'", other))
+        };
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>, String> {
+            patterns.iter()
+                .map(|pattern| Regex::new(pattern).map_err(|err| format!("invalid pattern '{}': {}", pattern, err)))
+                .collect()
+        };
+
+        Ok(ScopeOptions {
+            mode,
+            include: compile(&self.include)?,
+            exclude: compile(&self.exclude)?,
+            max_pages_per_host: self.max_pages_per_host,
+            ..ScopeOptions::default()
+        })
+    }
+}
+
+fn default_max_depth() -> usize {
+    1
+}
+
+#[derive(Debug, Serialize)]
+struct JobStatusJson {
+    id: u64,
+    seed_url: String,
+    state: &'static str,
+    pages_fetched: usize,
+    pages_failed: usize,
+    pages_unchanged: usize,
+    pages_queued: usize,
+    current_depth: usize,
+    links_skipped: usize
+}
+
+impl From<JobStatus> for JobStatusJson {
+    fn from(status: JobStatus) -> Self {
+        Self {
+            id: status.id,
+            seed_url: status.seed_url,
+            state: status.state.name(),
+            pages_fetched: status.pages_fetched,
+            pages_failed: status.pages_failed,
+            pages_unchanged: status.pages_unchanged,
+            pages_queued: status.pages_queued,
+            current_depth: status.current_depth,
+            links_skipped: status.links_skipped
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+
+    /// Byte offset into the query for query syntax errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>
+}
+
+/// Status code and JSON body of an API response.
+#[derive(Debug)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: String
+}
+
+impl ApiResponse {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        Self {
+            status,
+            body: serde_json::to_string(body).unwrap_or_else(|_| "{}".to_string())
+        }
+    }
+
+    fn error(status: u16, error: impl Into<String>) -> Self {
+        Self::json(status, &ErrorResponse { error: error.into(), position: None })
+    }
+
+    fn with_status(self, status: u16) -> Self {
+        Self { status, ..self }
+    }
+}
+
+/// The HTTP API: search over the index and control of crawl jobs.
+#[derive(Clone)]
+pub struct Api {
+    ctx: Arc<CTX>,
+    jobs: JobRegistry
+}
+
+impl Api {
+    pub fn new(ctx: Arc<CTX>, jobs: JobRegistry) -> Self {
+        Self { ctx, jobs }
+    }
+
+    /// Serves the API on `addr`. Blocks for as long as the server runs.
+    pub fn serve(&self, addr: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = tiny_http::Server::http(addr)?;
+        println!("Serving the search API on http://{}", addr);
+
+        let cors_headers: Vec<tiny_http::Header> = vec![
+            tiny_http::Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap(),
+            tiny_http::Header::from_bytes("Access-Control-Allow-Methods", "GET, POST, OPTIONS").unwrap(),
+            tiny_http::Header::from_bytes("Access-Control-Allow-Headers", "Content-Type, Authorization").unwrap(),
+        ];
+        let json_header = tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap();
+
+        for mut request in server.incoming_requests() {
+            if *request.method() == tiny_http::Method::Options {
+                let mut response = tiny_http::Response::empty(204);
+                for header in &cors_headers {
+                    response.add_header(header.clone());
+                }
+                _ = request.respond(response);
+                continue;
+            }
+
+            let mut body = String::new();
+            let api_response: ApiResponse = match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => self.handle(request.method(), request.url(), &body),
+                Err(_) => ApiResponse::error(400, "request body isn't valid UTF-8")
+            };
+
+            let mut response = tiny_http::Response::from_string(api_response.body)
+                .with_status_code(api_response.status)
+                .with_header(json_header.clone());
+            for header in &cors_headers {
+                response.add_header(header.clone());
+            }
+
+            if let Err(err) = request.respond(response) {
+                eprintln!("Couldn't send a response: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Routes one request. `url` is the request target, path plus query string.
+    pub fn handle(&self, method: &tiny_http::Method, url: &str, body: &str) -> ApiResponse {
+        let (path, query_string) = url.split_once('?').unwrap_or((url, ""));
+        let params: HashMap<String, String> = url::form_urlencoded::parse(query_string.as_bytes())
+            .into_owned()
+            .collect();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        use tiny_http::Method::{Get, Post};
+        match (method, segments.as_slice()) {
+            (Get, ["search"]) => search(&self.ctx, &params),
+            (Get, ["crawls"]) => {
+                let jobs: Vec<JobStatusJson> = self.jobs.list().into_iter().map(JobStatusJson::from).collect();
+                ApiResponse::json(200, &jobs)
+            },
+            (Post, ["crawls"]) => self.start_crawl(body),
+            (Get, ["crawls", id]) => self.job_action(id, |jobs, id| jobs.status(id)),
+            (Get, ["crawls", id, "failures"]) => self.failures(id),
+            (Post, ["crawls", id, "pause"]) => self.job_action(id, |jobs, id| jobs.pause(id)),
+            (Post, ["crawls", id, "resume"]) => self.job_action(id, |jobs, id| jobs.resume(id)),
+            (Post, ["crawls", id, "cancel"]) => self.job_action(id, |jobs, id| jobs.cancel(id)),
+            (_, ["search"] | ["crawls", ..]) => ApiResponse::error(405, "method not allowed"),
+            _ => ApiResponse::error(404, "not found")
+        }
+    }
+
+    /// `POST /crawls` with `{"seed_url": "...", "max_depth": 1}`
+    fn start_crawl(&self, body: &str) -> ApiResponse {
+        let request: StartCrawlRequest = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(err) => return ApiResponse::error(400, format!("invalid crawl request: {}", err))
+        };
+        if url::Url::parse(&request.seed_url).is_err() {
+            return ApiResponse::error(400, format!("invalid seed url '{}'", request.seed_url));
+        }
+        let scope: ScopeOptions = match request.scope_options() {
+            Ok(scope) => scope,
+            Err(err) => return ApiResponse::error(400, err)
+        };
+
+        let started = self.jobs.start(CrawlerOptions {
+            max_depth: request.max_depth,
+            seed_url: request.seed_url,
+            scope,
+            job_id: request.job_id,
+            ..CrawlerOptions::default()
+        });
+        let id: u64 = match started {
+            Ok(id) => id,
+            Err(err) => return ApiResponse::error(409, err.to_string())
+        };
+        self.job_action(&id.to_string(), |jobs, id| jobs.status(id))
+            .with_status(201)
+    }
+
+    /// `GET /crawls/{id}/failures`: the URLs the job failed on, with why.
+    fn failures(&self, id: &str) -> ApiResponse {
+        let Ok(id) = id.parse::<u64>() else {
+            return ApiResponse::error(404, "not found");
+        };
+
+        match self.jobs.failures(id) {
+            Ok(Some(failures)) => ApiResponse::json(200, &failures),
+            Ok(None) => ApiResponse::error(404, JobError::NotFound(id).to_string()),
+            Err(err) => {
+                eprintln!("Listing the failures of crawl job {} failed: {}", id, err);
+                ApiResponse::error(500, "internal error")
+            }
+        }
+    }
+
+    fn job_action(&self, id: &str, action: impl Fn(&JobRegistry, u64) -> Result<JobStatus, JobError>) -> ApiResponse {
+        let Ok(id) = id.parse::<u64>() else {
+            return ApiResponse::error(404, "not found");
+        };
+
+        match action(&self.jobs, id) {
+            Ok(status) => ApiResponse::json(200, &JobStatusJson::from(status)),
+            Err(err @ JobError::NotFound(_)) => ApiResponse::error(404, err.to_string()),
+            Err(err @ (JobError::InvalidTransition { .. } | JobError::AlreadyRunning(_))) => ApiResponse::error(409, err.to_string())
+        }
+    }
+}
+
+/// `GET /search?q=...&page=1&per_page=10&mode=and|or&ranking=bm25|bm25f|tfidf`
+fn search(ctx: &CTX, params: &HashMap<String, String>) -> ApiResponse {
+    let started = Instant::now();
+
+    let Some(query) = params.get("q").or_else(|| params.get("query")) else {
+        return ApiResponse::error(400, "missing query parameter 'q'");
+    };
+
+    let page: usize = match number_param(params, "page", 1) {
+        Ok(page) if page >= 1 => page,
+        _ => return ApiResponse::error(400, "'page' must be a positive number")
+    };
+    let per_page: usize = match number_param(params, "per_page", DEFAULT_PER_PAGE) {
+        Ok(per_page) if (1..=MAX_PER_PAGE).contains(&per_page) => per_page,
+        _ => return ApiResponse::error(400, format!("'per_page' must be between 1 and {}", MAX_PER_PAGE))
+    };
+
+    let mode: QueryMode = match params.get("mode").map(|mode| mode.to_lowercase()).as_deref() {
+        None | Some("and") => QueryMode::And,
+        Some("or") => QueryMode::Or,
+        Some(other) => return ApiResponse::error(400, format!("unknown mode '{}'", other))
+    };
+    let ranking: Ranking = match params.get("ranking").map(|ranking| ranking.to_lowercase()).as_deref() {
+        None | Some("bm25") => Ranking::default(),
+        Some("bm25f") => Ranking::Bm25F(Bm25F::default()),
+        Some("tfidf") => Ranking::TfIdf,
+        Some(other) => return ApiResponse::error(400, format!("unknown ranking '{}'", other))
+    };
+
+    let options = SearchOptions { mode, ranking, ..SearchOptions::default() };
+    match search_page(ctx, query, &options, page, per_page) {
+        Ok(SearchPage { results, total_hits, page, per_page }) => ApiResponse::json(200, &SearchResponse {
+            query: query.clone(),
+            results: results.into_iter()
+                .map(|result| SearchResultJson {
+                    url: result.url,
+                    title: result.title,
+                    snippet: result.snippet,
+                    score: result.score
+                })
+                .collect(),
+            total_hits,
+            page,
+            per_page,
+            took_ms: started.elapsed().as_secs_f64() * 1000.0
+        }),
+        Err(SearchError::Parse(err)) => ApiResponse::json(400, &ErrorResponse {
+            error: err.message,
+            position: Some(err.position)
+        }),
+        Err(err) => {
+            eprintln!("Search for '{}' failed: {}", query, err);
+            ApiResponse::error(500, "internal error")
+        }
+    }
+}
+
+fn number_param(params: &HashMap<String, String>, name: &str, default: usize) -> Result<usize, std::num::ParseIntError> {
+    params.get(name).map_or(Ok(default), |value| value.parse::<usize>())
+}
+
+#[cfg(test)]
+mod tests {
+    use indexer::{Document, Indexer};
+    use testkit::TestCtx;
+    use tiny_http::Method;
+
+    use super::*;
+
+    /// An API over three indexed pages about loops, which lives as long
+    /// as the returned context.
+    fn test_api(name: &str) -> (TestCtx, Api) {
+        let (ctx, indexer) = TestCtx::with(&format!("server-{}", name), |ctx| Arc::new(Indexer::new(ctx)));
+        for i in 0..3 {
+            indexer.create_index(&Document {
+                url: format!("https://example.com/loops/{}", i),
+                title: format!("Loops part {}", i),
+                text: "A for loop in python iterates over a sequence.".to_string(),
+                ..Document::default()
+            }).unwrap();
+        }
+        let api = Api::new(ctx.clone(), JobRegistry::new(indexer));
+        (ctx, api)
+    }
+
+    fn json(response: &ApiResponse) -> serde_json::Value {
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_search_returns_paginated_json() {
+        let (_ctx, api) = test_api("search");
+        let response = api.handle(&Method::Get, "/search?q=python+loop&page=2&per_page=2", "");
+        assert_eq!(response.status, 200);
+
+        let body = json(&response);
+        assert_eq!(body["total_hits"], 3);
+        assert_eq!(body["page"], 2);
+        assert_eq!(body["per_page"], 2);
+        assert_eq!(body["query"], "python loop");
+        assert!(body["took_ms"].is_number());
+
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0]["url"].as_str().unwrap().starts_with("https://example.com/loops/"));
+        assert_eq!(results[0]["snippet"], "A for loop in python iterates over a sequence.");
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests() {
+        let (_ctx, api) = test_api("bad-requests");
+        assert_eq!(api.handle(&Method::Get, "/search", "").status, 400);
+        assert_eq!(api.handle(&Method::Get, "/search?q=loop&per_page=1000", "").status, 400);
+        assert_eq!(api.handle(&Method::Get, "/search?q=loop&ranking=pagerank", "").status, 400);
+        assert_eq!(api.handle(&Method::Post, "/search?q=loop", "").status, 405);
+        assert_eq!(api.handle(&Method::Get, "/nothing", "").status, 404);
+
+        let response = api.handle(&Method::Get, "/search?q=python%20AND", "");
+        assert_eq!(response.status, 400);
+        assert_eq!(json(&response)["position"], 10);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_job_lifecycle() {
+        let (_ctx, api) = test_api("crawls");
+
+        // the job task doesn't get to run before the test yields, so it's still queued
+        let response = api.handle(&Method::Post, "/crawls", r#"{"seed_url": "http://127.0.0.1:9/", "max_depth": 0}"#);
+        assert_eq!(response.status, 201);
+        let id = json(&response)["id"].as_u64().unwrap();
+
+        let status = json(&api.handle(&Method::Get, &format!("/crawls/{}", id), ""));
+        assert_eq!(status["seed_url"], "http://127.0.0.1:9/");
+        assert_eq!(json(&api.handle(&Method::Get, "/crawls", "")).as_array().unwrap().len(), 1);
+
+        assert_eq!(api.handle(&Method::Post, &format!("/crawls/{}/pause", id), "").status, 409);
+        let response = api.handle(&Method::Post, &format!("/crawls/{}/cancel", id), "");
+        assert_eq!(json(&response)["state"], "cancelled");
+        assert_eq!(api.handle(&Method::Post, &format!("/crawls/{}/resume", id), "").status, 409);
+
+        // a finished job can be started again under its id, but not twice at once
+        let body = format!(r#"{{"seed_url": "http://127.0.0.1:9/", "max_depth": 0, "job_id": {}}}"#, id);
+        assert_eq!(api.handle(&Method::Post, "/crawls", &body).status, 201);
+        assert_eq!(api.handle(&Method::Post, "/crawls", &body).status, 409);
+
+        assert_eq!(api.handle(&Method::Get, "/crawls/42", "").status, 404);
+        assert_eq!(api.handle(&Method::Post, "/crawls", r#"{"seed_url": "not a url"}"#).status, 400);
+        assert_eq!(api.handle(&Method::Post, "/crawls", r#"{"seed_url": "http://127.0.0.1:9/", "include": ["("]}"#).status, 400);
+        assert_eq!(api.handle(&Method::Post, "/crawls", r#"{"seed_url": "http://127.0.0.1:9/", "scope": "galaxy"}"#).status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_failures_are_listed() {
+        let (_ctx, api) = test_api("failures");
+        let response = api.handle(&Method::Post, "/crawls", r#"{"seed_url": "http://127.0.0.1:9/", "max_depth": 0}"#);
+        let id = json(&response)["id"].as_u64().unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while json(&api.handle(&Method::Get, &format!("/crawls/{}", id), ""))["state"] != "done" {
+            assert!(std::time::Instant::now() < deadline, "crawl job {} didn't finish in time", id);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // nothing listens on port 9, so the seed is unreachable, not disallowed
+        let response = api.handle(&Method::Get, &format!("/crawls/{}/failures", id), "");
+        assert_eq!(response.status, 200);
+        let failures = json(&response);
+        assert_eq!(failures.as_array().unwrap().len(), 1);
+        assert_eq!(failures[0]["url"], "http://127.0.0.1:9/");
+        assert_eq!(failures[0]["kind"], "network");
+        assert_eq!(failures[0]["job_id"], id);
+
+        assert_eq!(api.handle(&Method::Get, "/crawls/42/failures", "").status, 404);
+    }
+}
@@ -1,19 +1,66 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::Duration
+};
 
-use crawler::{Crawler, CrawlerOptions};
+use context::{CTXOptions, CTX};
+use crawler::{now_secs, CrawlerOptions, JobRegistry, PageRankJob, PageRankOptions, RecrawlScheduler};
 use indexer::Indexer;
 
+mod api;
+
+const API_ADDR: &str = "127.0.0.1:5000";
+
+/// How often the server looks for pages due for a recrawl.
+const RECRAWL_CHECK_EVERY: Duration = Duration::from_secs(10 * 60);
+
+/// How often PageRank is recomputed over the link graph.
+const PAGERANK_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// Runs the HTTP API. Crawls are started through `POST /crawls`; seed
+/// URLs passed on the command line are queued right away, and pages due
+/// for a revisit are recrawled and PageRank updated in the background.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let indexer: Arc<Indexer> = Arc::new(Indexer{});
+    let context: Arc<CTX> = Arc::new(CTX::new(CTXOptions::default()));
+    let indexer: Arc<Indexer> = Arc::new(Indexer::new(context.clone()));
+    let jobs: JobRegistry = JobRegistry::new(indexer.clone());
+    tokio::spawn(schedule_recrawls(RecrawlScheduler::new(jobs.clone(), context.rocks_con.clone()), RECRAWL_CHECK_EVERY));
+    tokio::spawn(update_pageranks(PageRankJob::new(indexer, PageRankOptions::default()), PAGERANK_EVERY));
 
-    let cr: Crawler = Crawler::new(
-        indexer.clone(), 
-        CrawlerOptions { 
-            max_depth: 1, 
-            seed_url: "https://docs.python.org/3/reference/compound_stmts.html".to_string(), 
+    for seed_url in std::env::args().skip(1) {
+        let id: u64 = jobs.start(CrawlerOptions { seed_url: seed_url.clone(), ..CrawlerOptions::default() })?;
+        println!("Started crawl job {} for {}", id, seed_url);
+    }
+
+    let api = api::Api::new(context, jobs);
+    tokio::task::spawn_blocking(move || api.serve(API_ADDR)).await?
+        .map_err(|err| err as Box<dyn std::error::Error>)
+}
+
+/// Starts the recrawls that are due, every `every`.
+async fn schedule_recrawls(scheduler: RecrawlScheduler, every: Duration) {
+    let mut ticks = tokio::time::interval(every);
+    loop {
+        ticks.tick().await;
+        match scheduler.run_once(now_secs()) {
+            Ok(ids) if !ids.is_empty() => println!("Started recrawl jobs {:?}", ids),
+            Ok(_) => {},
+            Err(err) => eprintln!("Recrawl scheduling failed: {}", err)
         }
-    );
+    }
+}
 
-    cr.start_crawling().await
-}
\ No newline at end of file
+/// Recomputes PageRank every `every`.
+async fn update_pageranks(job: PageRankJob, every: Duration) {
+    let mut ticks = tokio::time::interval(every);
+    loop {
+        ticks.tick().await;
+        let job: PageRankJob = job.clone();
+        match tokio::task::spawn_blocking(move || job.run_once()).await {
+            Ok(Ok(ranked)) => println!("Updated the PageRank of {} documents", ranked),
+            Ok(Err(err)) => eprintln!("PageRank update failed: {}", err),
+            Err(err) => eprintln!("PageRank update panicked: {}", err)
+        }
+    }
+}